@@ -6,80 +6,333 @@
 //! [www.projectwonderful.com/abouttheinfiniteauction.php](https://web.archive.org/web/20180612112237/https://www.projectwonderful.com/abouttheinfiniteauction.php)
 
 use project_brilliant_utilities::{
-    Second, Currency, Token,
-    SECONDS_PER_DAY
+    Second, Timestamp, Currency, Token, SaturatingMul
 };
 
-use std::cmp::{min, max};
+use std::cmp::{min, max, Reverse};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::mem::take;
+use std::ops::Bound::{Excluded, Unbounded};
 
-#[derive(Debug)]
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Bid<T: Copy> {
+    /// The most this bid will pay each second. To take bids as "$5 a day"
+    /// or "$30 a week", use [`Rate::per_second`].
+    ///
+    ///   [`Rate::per_second`]: ../project_brilliant_utilities/struct.Rate.html#method.per_second
     pub bid: Currency,
+    /// The most this bid will spend, all told.
     pub expense_limit: Token,
-    pub expiry: Second,
+    pub expiry: Timestamp,
     pub data: T
 }
 
-fn winning_bid<'a, T: Copy>(
-    bids: Vec<&'a mut Bid<T>>,
-    increment: Currency, min_bid: Currency
-) -> Option<(&'a mut Bid<T>, Currency, Second)> {
-    let mut bids: Vec<_> = bids.into_iter()
-                               .filter(|x| x.bid >= min_bid)
-                               .collect();
-    bids.sort_by(|a, b| b.bid.cmp(&a.bid));
-
-    let mut winner = loop {
-        let bid = match bids.pop() {
-            Some(x) => x,
-            None => return None
-        };
-        if min_bid * Second::from(1) <= bid.expense_limit {
-            break bid;
+/// The rules of one ad box's auction.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AuctionParams {
+    /// How much more than the runner-up's bid the winner pays, if it can.
+    pub increment: Currency,
+    pub min_bid: Currency
+}
+
+impl AuctionParams {
+    /// The fewest tokens a bid can have left and still be shown.
+    fn min_tokens(&self) -> Token {
+        max(self.min_bid * Second::from(1), 1.into())
+    }
+}
+
+/// Why [`validate_bid`] says a bid will never be shown.
+///
+///   [`validate_bid`]: fn.validate_bid.html
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BidRejection {
+    ZeroBid,
+    BelowMinimum { min_bid: Currency },
+    /// The expense limit won't pay for a single second at the minimum bid.
+    CantCoverOneSecond { needed: Token, expense_limit: Token },
+    ExpiryInPast { expiry: Timestamp, now: Timestamp },
+    /// The bid's so big that working out what it'd take to beat it would
+    /// overflow.
+    OverflowRisk
+}
+
+impl fmt::Display for BidRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BidRejection::ZeroBid => write!(f, "the bid is zero"),
+            BidRejection::BelowMinimum { min_bid } => write!(
+                f, "the bid is below the ad box's minimum bid of {}",
+                min_bid.per_day()
+            ),
+            BidRejection::CantCoverOneSecond { .. } => write!(
+                f, "the expense limit won't pay for a second of display"
+            ),
+            BidRejection::ExpiryInPast { .. } => write!(
+                f, "the bid has already expired"
+            ),
+            BidRejection::OverflowRisk => write!(
+                f, "the bid is far too large"
+            )
         }
-    };
-    let mut bid_amount = min(winner.bid, min_bid);
-    let mut to_beat = winner.bid + increment;
-    let mut expiry = winner.expiry;
-
-    for bid in bids.into_iter().rev() {
-        if bid.expense_limit >= to_beat * Second::from(1) {
-            expiry = min(winner.expiry, bid.expiry);
-            bid_amount = min(bid.bid, to_beat);
-            to_beat = bid.bid + increment;
-            winner = bid;
+    }
+}
+
+impl Error for BidRejection {}
+
+/// Something that went wrong while running an auction. None of these
+/// should happen to bids that pass [`validate_bid`].
+///
+///   [`validate_bid`]: fn.validate_bid.html
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AuctionError {
+    /// A bid was due to be shown from `now`, but it had expired already.
+    ExpiryInPast { expiry: Timestamp, now: Timestamp },
+    /// A bid won, but couldn't pay for a single second, so the auction
+    /// would never have moved on.
+    Stalled { bid: BidId, at: Timestamp },
+    /// The auction lost track of a bid.
+    UnknownBid(BidId)
+}
+
+impl fmt::Display for AuctionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuctionError::ExpiryInPast { expiry, now } => write!(
+                f, "bid expired at {:?}, before {:?}", expiry, now
+            ),
+            AuctionError::Stalled { bid, at } => write!(
+                f, "{:?} won at {:?} but can't pay for a second", bid, at
+            ),
+            AuctionError::UnknownBid(bid) => write!(
+                f, "{:?} is missing from the auction", bid
+            )
         }
     }
+}
+
+impl Error for AuctionError {}
+
+/// A stretch of time for which one bid was shown, at one price.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Segment<T: Copy> {
+    pub start: Timestamp,
+    pub end: Timestamp,
+    /// The winning bid's `data`.
+    pub winner: T,
+    /// What the winner was charged per second: the least it needed to bid
+    /// to beat the runner-up (or to meet the minimum bid, if there wasn't
+    /// one), but never more than it actually bid.
+    pub rate: Currency,
+    pub spent: Token,
+    /// The `data` of the bid that the winner had to beat, if any.
+    pub runner_up: Option<T>
+}
+
+/// Identifies a bid held by an [`AuctionState`].
+///
+///   [`AuctionState`]: struct.AuctionState.html
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BidId(pub u64);
+
+/// A change made to a bid after it's been placed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Amendment {
+    /// Raise or lower the bid.
+    Bid(Currency),
+    /// Change the expense limit. This limits everything the bid ever
+    /// spends, so whatever it's spent already counts towards the new limit.
+    ExpenseLimit(Token)
+}
+
+/// Something the auction decided about a bid, and why, as recorded by
+/// [`run_auction_traced`]. Bids are identified by their `data`.
+///
+///   [`run_auction_traced`]: fn.run_auction_traced.html
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Decision<T: Copy> {
+    /// The bid was under the minimum bid, so it wasn't considered at all.
+    Filtered { bid: T, min_bid: Currency },
+    /// The bid couldn't afford even a second at the price it'd have had to
+    /// pay to take the lead, so it was passed over.
+    Skipped { bid: T, needed: Token, expense_limit: Token },
+    /// The bid took the lead from `outbid`, at `rate`. If `outbid` is
+    /// `None`, this was the first bid that could pay the minimum bid.
+    Outbid { bid: T, outbid: Option<T>, rate: Currency },
+    /// The bid expired, and left the auction.
+    Expired { bid: T, at: Timestamp },
+    /// The bid's expense limit ran too low to pay for another second, so it
+    /// left the auction.
+    Exhausted { bid: T, at: Timestamp, remaining: Token },
+    /// The bid failed [`validate_bid`] when it was placed, so it never
+    /// entered the auction.
+    ///
+    ///   [`validate_bid`]: fn.validate_bid.html
+    Rejected { bid: T, reason: BidRejection }
+}
 
-    Some((winner, bid_amount, expiry))
+/// A segment, and the decisions that led to it and ended it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TracedSegment<T: Copy> {
+    pub segment: Segment<T>,
+    pub decisions: Vec<Decision<T>>
 }
 
-/// Filter bids for valid ones.
+/// Everything [`run_auction_traced`] decided.
 ///
-/// The three checks this performs are:
+///   [`run_auction_traced`]: fn.run_auction_traced.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AuctionTrace<T: Copy> {
+    /// Bids thrown out before anything was shown.
+    pub dropped: Vec<Decision<T>>,
+    pub segments: Vec<TracedSegment<T>>
+}
+
+/// Something that happens to a bid, for [`replay_auction`].
 ///
-///   * `bid`[`.bid`]` > `[`Currency`]`(0)`
-///   * `bid`[`.expense_limit`]` > `[`Token`]`(0)`
-///   * `bid`[`.expiry`]` > now`
+///   [`replay_auction`]: fn.replay_auction.html
+#[derive(Clone, Debug)]
+pub enum BidEvent<T: Copy> {
+    Place(Bid<T>),
+    Amend(Amendment),
+    Cancel
+}
+
+/// Work out which bid wins, which bid it beat, what it pays and until when
+/// that price holds.
 ///
-/// These checks are better implemented in SQL (or something), since
-/// this function is rather inefficient. Nevertheless, since other
-/// functions expect to recieve only valid bids, this function may
-/// occasionally be necessary.
+/// `bids` must be in ascending order of priority: lowest bid first, and
+/// amongst equal bids, the most recently placed first. (First come, first
+/// served.) If there's a `trace`, every decision is recorded in it.
+fn winning_bid<'a, K: Copy, T: 'a + Copy>(
+    bids: impl IntoIterator<Item = (K, &'a Bid<T>)>,
+    increment: Currency, min_bid: Currency,
+    mut trace: Option<&mut Vec<Decision<T>>>
+) -> Option<(K, Option<K>, Currency, Timestamp)> {
+    bids.into_iter().fold(None, |scan, (key, bid)| scan_bid(
+        scan, key, bid, increment, min_bid, trace.as_deref_mut()
+    )).map(|scan| (scan.winner, scan.runner_up, scan.rate, scan.expiry))
+}
+
+/// How far [`winning_bid`] has got through the bids: who's winning so far.
 ///
-///   [`.bid`]: struct.Bid.html#structfield.bid
-///   [`.expense_limit`]: struct.Bid.html#structfield.expense_limit
-///   [`.expiry`]: struct.Bid.html#structfield.expiry
-///   [`Currency`]: ../project_brilliant_utilities/struct.Currency.html
-///   [`Token`]: ../project_brilliant_utilities/struct.Token.html
-#[deprecated(note="Refactor to avoid using this function.")]
-pub fn valid_bids<'a, T: Copy>(
-    bids: &'a [Bid<T>],
-    now: Second
-) -> Vec<&'a Bid<T>> {
-    bids.into_iter().filter(|bid| bid.bid > 0.into()
-                               && bid.expense_limit > 0.into()
-                               && bid.expiry > now).collect()
+///   [`winning_bid`]: fn.winning_bid.html
+#[derive(Copy, Clone, Debug)]
+struct Scan<K: Copy, T: Copy> {
+    winner: K,
+    winning: Bid<T>,
+    runner_up: Option<K>,
+    rate: Currency,
+    to_beat: Currency,
+    expiry: Timestamp
+}
+
+/// Take the next bid into account, for [`winning_bid`]. `scan` is `None`
+/// until a bid is good enough to win.
+///
+///   [`winning_bid`]: fn.winning_bid.html
+fn scan_bid<K: Copy, T: Copy>(
+    scan: Option<Scan<K, T>>, key: K, bid: &Bid<T>,
+    increment: Currency, min_bid: Currency,
+    trace: Option<&mut Vec<Decision<T>>>
+) -> Option<Scan<K, T>> {
+    let record = |decision| if let Some(trace) = trace {
+        trace.push(decision);
+    };
+
+    // Since the bids are in order, everything after the first winner is
+    // big enough.
+    let scan = match scan {
+        Some(scan) => scan,
+        None => {
+            let needed = min_bid * Second::from(1);
+            if bid.bid < min_bid {
+                record(Decision::Filtered { bid: bid.data, min_bid });
+                return None;
+            } else if needed > bid.expense_limit {
+                record(Decision::Skipped {
+                    bid: bid.data, needed, expense_limit: bid.expense_limit
+                });
+                return None;
+            }
+            let rate = min(bid.bid, min_bid);
+            record(Decision::Outbid { bid: bid.data, outbid: None, rate });
+            return Some(Scan {
+                winner: key,
+                winning: *bid,
+                runner_up: None,
+                rate,
+                to_beat: bid.bid.saturating_add(increment),
+                expiry: bid.expiry
+            });
+        }
+    };
+
+    let needed = scan.to_beat * Second::from(1);
+    if bid.expense_limit >= needed {
+        let rate = min(bid.bid, scan.to_beat);
+        record(Decision::Outbid {
+            bid: bid.data, outbid: Some(scan.winning.data), rate
+        });
+        Some(Scan {
+            winner: key,
+            winning: *bid,
+            runner_up: Some(scan.winner),
+            rate,
+            to_beat: bid.bid.saturating_add(increment),
+            expiry: min(scan.winning.expiry, bid.expiry)
+        })
+    } else {
+        record(Decision::Skipped {
+            bid: bid.data, needed, expense_limit: bid.expense_limit
+        });
+        Some(scan)
+    }
+}
+
+/// Check that a bid could be shown in an auction with these `params`,
+/// starting at `now`, and if not, say why not.
+///
+/// Bids that fail this check are dropped by [`run_auction`] and
+/// [`AuctionState`] as soon as they're placed.
+///
+///   [`run_auction`]: fn.run_auction.html
+///   [`AuctionState`]: struct.AuctionState.html
+pub fn validate_bid<T: Copy>(
+    bid: &Bid<T>, params: &AuctionParams, now: Timestamp
+) -> Result<(), BidRejection> {
+    if bid.bid == 0.into() {
+        return Err(BidRejection::ZeroBid);
+    }
+    if bid.bid < params.min_bid {
+        return Err(BidRejection::BelowMinimum { min_bid: params.min_bid });
+    }
+    if bid.expiry <= now {
+        return Err(BidRejection::ExpiryInPast { expiry: bid.expiry, now });
+    }
+    let needed = params.min_tokens();
+    if bid.expense_limit < needed {
+        return Err(BidRejection::CantCoverOneSecond {
+            needed, expense_limit: bid.expense_limit
+        });
+    }
+    // The auction works out `bid + increment` to see what beats it.
+    if bid.bid.checked_add(params.increment).is_none() {
+        return Err(BidRejection::OverflowRisk);
+    }
+    Ok(())
 }
 
 /// Find the end point of a bid, so a timer can be set.
@@ -91,63 +344,456 @@ pub fn valid_bids<'a, T: Copy>(
 /// suboptimal.
 fn find_end(
     amount: Currency, limit: Token,
-    expiry: Second, now: Second
-) -> (Second, Token) {
+    expiry: Timestamp, now: Timestamp
+) -> Result<(Timestamp, Token), AuctionError> {
     if expiry <= now {
-        panic!(
-            "Expiry date ({:?}) in the past (before {:?})! Bid invalid.",
-                          expiry,                   now
-        );
+        return Err(AuctionError::ExpiryInPast { expiry, now });
     }
     if amount == 0.into() {
-        return (expiry, 0.into());
+        return Ok((expiry, 0.into()));
     }
-    let broke = (limit / amount) + now;
-    if broke > expiry {
-        return (expiry, (expiry - now) * amount)
+    // Measured from `now`, so that nothing here can overflow.
+    let broke = limit / amount;
+    if broke > expiry - now {
+        return Ok((expiry, (expiry - now).saturating_mul(amount)));
     }
-    return (broke, limit / amount * amount);
+    Ok((now.saturating_add(broke), broke.saturating_mul(amount)))
 }
 
+/// Work out everything that'll be shown from `now` on, if nobody changes
+/// their bids.
+///
+/// Any [`BidId`] in an error is the bid's index in `bids`.
+///
+///   [`BidId`]: struct.BidId.html
 pub fn run_auction<T: Copy>(
-    mut bids: Vec<Bid<T>>, increment: Currency,
-    min_bid: Currency, mut now: Second
-) -> Vec<(T, Second, Token)> {
-    // Any less than this and it hasn't a chance.
-    let min_tokens = max(min_bid * Second::from(1), 1.into());
-
-    // Initial filtration.
-    bids.retain(|bid| bid.expiry > now
-                   && bid.bid >= min_bid
-                   && bid.expense_limit >= min_tokens);
-
-    // TODO: Profiling to determine best initial capacity.
-    let mut output = Vec::with_capacity(bids.len());  // not max!
-    'outer: while !bids.is_empty() {
-        let (bid, bid_amount, expiry) = winning_bid(
-            bids.iter_mut().collect(),
-            increment, min_bid
-        ).unwrap();
+    bids: Vec<Bid<T>>, increment: Currency,
+    min_bid: Currency, now: Timestamp
+) -> Result<Vec<Segment<T>>, AuctionError> {
+    let mut state = AuctionState::new(increment, min_bid, now);
+    for (id, bid) in bids.into_iter().enumerate() {
+        state.place_bid(BidId(id as u64), bid);
+    }
+    let mut pieces = Vec::new();
+    state.advance(Timestamp::max_value(), &mut pieces)?;
+    Ok(pieces.into_iter().map(|piece| piece.segment).collect())
+}
+
+/// [`run_auction`], but keeping a record of every decision made along the
+/// way, for when somebody wants to know why.
+///
+///   [`run_auction`]: fn.run_auction.html
+pub fn run_auction_traced<T: Copy>(
+    bids: Vec<Bid<T>>, increment: Currency,
+    min_bid: Currency, now: Timestamp
+) -> Result<AuctionTrace<T>, AuctionError> {
+    let mut state = AuctionState::new(increment, min_bid, now);
+    state.trace = Some(Vec::new());
+    for (id, bid) in bids.into_iter().enumerate() {
+        state.place_bid(BidId(id as u64), bid);
+    }
+    let dropped = state.trace.replace(Vec::new()).unwrap_or_default();
+    let mut pieces = Vec::new();
+    state.advance(Timestamp::max_value(), &mut pieces)?;
+    let segments = pieces.into_iter().map(
+        |piece| TracedSegment {
+            segment: piece.segment,
+            decisions: piece.decisions
+        }
+    ).collect();
+    Ok(AuctionTrace { dropped, segments })
+}
+
+/// Run an auction in which bids are placed, amended and cancelled as time
+/// goes on, starting at `now`.
+///
+/// `events` must be in chronological order. Each one takes effect at its
+/// time, so whatever was spent before then stays spent. Returns the same
+/// as [`run_auction`].
+///
+///   [`run_auction`]: fn.run_auction.html
+pub fn replay_auction<T: Copy>(
+    events: impl IntoIterator<Item = (Timestamp, BidId, BidEvent<T>)>,
+    increment: Currency, min_bid: Currency, now: Timestamp
+) -> Result<Vec<Segment<T>>, AuctionError> {
+    let mut state = AuctionState::new(increment, min_bid, now);
+    let mut pieces = Vec::new();
+    for (at, id, event) in events {
+        state.advance(at, &mut pieces)?;
+        match event {
+            BidEvent::Place(bid) => state.place_bid(id, bid),
+            BidEvent::Amend(amendment) => state.amend_bid(id, amendment),
+            BidEvent::Cancel => state.cancel_bid(id)
+        };
+    }
+    state.advance(Timestamp::max_value(), &mut pieces)?;
+
+    // Every event cuts short whatever's being shown at the time; stitch it
+    // back together wherever nothing actually changed.
+    let mut output: Vec<Piece<T>> = Vec::with_capacity(pieces.len());
+    for piece in pieces {
+        match output.last_mut() {
+            Some(last) if last.cut_short
+                       && last.winner == piece.winner
+                       && last.runner_up == piece.runner_up
+                       && last.segment.rate == piece.segment.rate => {
+                last.segment.end = piece.segment.end;
+                last.segment.spent = last.segment.spent
+                                         .saturating_add(piece.segment.spent);
+                last.decisions.extend(piece.decisions);
+                last.cut_short = piece.cut_short;
+            },
+            _ => output.push(piece)
+        }
+    }
+    Ok(output.into_iter().map(|piece| piece.segment).collect())
+}
+
+#[derive(Debug)]
+struct Entry<T: Copy> {
+    seq: u64,
+    spent: Token,
+    bid: Bid<T>
+}
+
+/// Part (or all) of a segment, as returned by `AuctionState::advance`.
+#[derive(Debug)]
+struct Piece<T: Copy> {
+    winner: BidId,
+    runner_up: Option<BidId>,
+    segment: Segment<T>,
+    decisions: Vec<Decision<T>>,
+    cut_short: bool
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Current<T: Copy> {
+    winner: BidId,
+    runner_up: Option<(BidId, T)>,
+    rate: Currency,
+    end: Timestamp
+}
+
+/// Where a bid comes in `AuctionState::ranking`: by amount, then first
+/// come, first served.
+type Rank = (Currency, Reverse<u64>);
+
+/// The live auction for one ad box.
+///
+/// Where [`run_auction`] works out the whole future from scratch, this
+/// holds on to the bids (kept in priority order) and only works out the
+/// segment that's being shown right now, and only when it's asked for. A
+/// new or cancelled bid therefore costs a few tree operations, not a full
+/// re-run; running the state with no changes gives the same timeline as
+/// [`run_auction`].
+///
+/// Working out the winner means going through the bids from the lowest
+/// up, so how far that had got after each bid is kept too. A bid changing
+/// can only affect the outcome from that bid up, so only the bids above it
+/// are gone through again. (Unless the decisions are being traced, in
+/// which case every bid is gone through every time, to record them all.)
+///
+///   [`run_auction`]: fn.run_auction.html
+#[derive(Debug)]
+pub struct AuctionState<T: Copy> {
+    params: AuctionParams,
+    now: Timestamp,
+    next_seq: u64,
+    bids: HashMap<BidId, Entry<T>>,
+    ranking: BTreeMap<Rank, BidId>,
+    /// How far `winning_bid` had got after each bid in `ranking`, for as
+    /// many bids from the bottom as haven't changed since.
+    scans: BTreeMap<Rank, Option<Scan<BidId, T>>>,
+    expiries: BTreeSet<(Timestamp, BidId)>,
+    current: Option<Option<Current<T>>>,  // outer None: needs working out
+    trace: Option<Vec<Decision<T>>>
+}
+
+impl<T: Copy> AuctionState<T> {
+    pub fn new(
+        increment: Currency, min_bid: Currency, now: Timestamp
+    ) -> AuctionState<T> {
+        AuctionState {
+            params: AuctionParams { increment, min_bid },
+            now,
+            next_seq: 0,
+            bids: HashMap::new(),
+            ranking: BTreeMap::new(),
+            scans: BTreeMap::new(),
+            expiries: BTreeSet::new(),
+            current: Some(None),
+            trace: None
+        }
+    }
+
+    pub fn now(&self) -> Timestamp {
+        self.now
+    }
+
+    /// The live bids, in no particular order.
+    pub fn bids(&self) -> impl Iterator<Item = (BidId, &Bid<T>)> {
+        self.bids.iter().map(|(&id, entry)| (id, &entry.bid))
+    }
+
+    pub fn get_bid(&self, id: BidId) -> Option<&Bid<T>> {
+        self.bids.get(&id).map(|entry| &entry.bid)
+    }
+
+    /// Place a bid, replacing (and returning) any bid already placed with
+    /// the same `id`.
+    ///
+    /// Bids that fail [`validate_bid`] could never be shown, so they're
+    /// dropped straight away.
+    ///
+    ///   [`validate_bid`]: fn.validate_bid.html
+    pub fn place_bid(&mut self, id: BidId, bid: Bid<T>) -> Option<Bid<T>> {
+        let old = self.remove(id);
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.insert(id, Entry { seq, spent: 0.into(), bid });
+        old.map(|entry| entry.bid)
+    }
+
+    /// Amend a live bid, returning it as it was before.
+    ///
+    /// The bid keeps what it's spent so far, and its place in the queue
+    /// amongst equal bids. As with [`place_bid`], if the amended bid could
+    /// never be shown, it's dropped.
+    ///
+    ///   [`place_bid`]: #method.place_bid
+    pub fn amend_bid(
+        &mut self, id: BidId, amendment: Amendment
+    ) -> Option<Bid<T>> {
+        let Entry { seq, spent, bid: old } = self.remove(id)?;
+        let mut bid = old;
+        match amendment {
+            Amendment::Bid(amount) => bid.bid = amount,
+            Amendment::ExpenseLimit(limit) => {
+                bid.expense_limit = limit.saturating_sub(spent);
+            }
+        }
+        self.insert(id, Entry { seq, spent, bid });
+        Some(old)
+    }
+
+    pub fn cancel_bid(&mut self, id: BidId) -> Option<Bid<T>> {
+        self.remove(id).map(|entry| entry.bid)
+    }
+
+    /// How much a live bid has spent since it was placed.
+    pub fn spent(&self, id: BidId) -> Option<Token> {
+        self.bids.get(&id).map(|entry| entry.spent)
+    }
+
+    /// The bid being shown right now, if any.
+    pub fn current_winner(
+        &mut self
+    ) -> Result<Option<(BidId, &Bid<T>)>, AuctionError> {
+        Ok(match self.current()? {
+            Some(current) => Some((
+                current.winner,
+                &self.bids.get(&current.winner)
+                          .ok_or(AuctionError::UnknownBid(current.winner))?
+                          .bid
+            )),
+            None => None
+        })
+    }
+
+    /// When the segment being shown right now ends, if there is one: the
+    /// next time anything changes, unless the bids do, so a timer can be
+    /// set for it.
+    pub fn segment_end(
+        &mut self
+    ) -> Result<Option<Timestamp>, AuctionError> {
+        Ok(self.current()?.map(|current| current.end))
+    }
+
+    /// Move the clock forward to `now`, charging the winning bids as it
+    /// goes. Advancing to a time that's already passed does nothing.
+    ///
+    /// Returns what was shown in the meantime. If a segment is still
+    /// running at `now`, it's cut short there, and the rest of it will be
+    /// returned by the next call.
+    ///
+    /// If the auction goes wrong partway, the clock stops where it went
+    /// wrong: whatever was shown up until then is returned as normal, and
+    /// the error is returned by the next call. That way nothing that's
+    /// been charged for goes missing.
+    pub fn advance_to(
+        &mut self, now: Timestamp
+    ) -> Result<Vec<Segment<T>>, AuctionError> {
+        let mut pieces = Vec::new();
+        match self.advance(now, &mut pieces) {
+            Err(e) if pieces.is_empty() => Err(e),
+            _ => Ok(pieces.into_iter().map(|piece| piece.segment).collect())
+        }
+    }
+
+    /// Like `advance_to`, but adding to `output`, and failing however much
+    /// has been shown.
+    fn advance(
+        &mut self, now: Timestamp, output: &mut Vec<Piece<T>>
+    ) -> Result<(), AuctionError> {
+        while self.now < now {
+            let current = match self.current()? {
+                Some(x) => x,
+                None => {
+                    self.now = now;
+                    break;
+                }
+            };
+            let end = min(current.end, now);
+            let spent = current.rate.saturating_mul(end - self.now);
+            let entry = self.bids.get_mut(&current.winner)
+                            .ok_or(AuctionError::UnknownBid(current.winner))?;
+            entry.bid.expense_limit = entry.bid.expense_limit
+                                           .saturating_sub(spent);
+            entry.spent = entry.spent.saturating_add(spent);
+            // It's got less to spend, so it might not win from here on.
+            self.scans.split_off(&(entry.bid.bid, Reverse(entry.seq)));
+            output.push(Piece {
+                winner: current.winner,
+                runner_up: current.runner_up.map(|(id, _)| id),
+                segment: Segment {
+                    start: self.now, end,
+                    winner: entry.bid.data,
+                    rate: current.rate,
+                    spent,
+                    runner_up: current.runner_up.map(|(_, data)| data)
+                },
+                decisions: self.trace.as_mut()
+                                     .map(take)
+                                     .unwrap_or_default(),
+                cut_short: end < current.end
+            });
+            let (data, limit) = (entry.bid.data, entry.bid.expense_limit);
+            self.now = end;
+
+            if end == current.end {
+                self.current = None;
+                if limit < self.params.min_tokens() {
+                    self.record(Decision::Exhausted {
+                        bid: data, at: end, remaining: limit
+                    });
+                    self.remove(current.winner);
+                }
+                self.remove_expired();
+                if let (Some(trace), Some(last)) =
+                       (self.trace.as_mut(), output.last_mut()) {
+                    last.decisions.append(trace);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Add an entry, unless it fails [`validate_bid`]. There mustn't
+    /// already be an entry with this `id`.
+    ///
+    ///   [`validate_bid`]: fn.validate_bid.html
+    fn insert(&mut self, id: BidId, entry: Entry<T>) {
+        let bid = &entry.bid;
+        match validate_bid(bid, &self.params, self.now) {
+            Ok(()) => {
+                let key = (bid.bid, Reverse(entry.seq));
+                self.ranking.insert(key, id);
+                self.scans.split_off(&key);
+                self.expiries.insert((bid.expiry, id));
+                self.bids.insert(id, entry);
+                self.current = None;
+            },
+            Err(reason) => self.record(Decision::Rejected {
+                bid: bid.data, reason
+            })
+        }
+    }
+
+    fn remove(&mut self, id: BidId) -> Option<Entry<T>> {
+        let entry = self.bids.remove(&id)?;
+        let key = (entry.bid.bid, Reverse(entry.seq));
+        self.ranking.remove(&key);
+        self.scans.split_off(&key);
+        self.expiries.remove(&(entry.bid.expiry, id));
+        self.current = None;
+        Some(entry)
+    }
 
-        let end = find_end(bid_amount, bid.expense_limit,
-                           expiry, now);
-        now = end.0;
-        let spent = end.1;
-        assert!(bid_amount == 0.into() || spent > 0.into(),
-                "Stuck in an infinite loop!");
-        bid.expense_limit -= spent;
+    fn remove_expired(&mut self) {
+        while let Some(&(expiry, id)) = self.expiries.iter().next() {
+            if expiry > self.now {
+                break;
+            }
+            self.expiries.remove(&(expiry, id));
+            if let Some(entry) = self.remove(id) {
+                self.record(Decision::Expired {
+                    bid: entry.bid.data, at: expiry
+                });
+            }
+        }
+    }
 
-        output.push((bid.data, now, spent));
+    fn record(&mut self, decision: Decision<T>) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.push(decision);
+        }
+    }
 
-        bids.retain(|bid| bid.expiry > now
-                       && bid.expense_limit >= min_tokens);
+    fn current(&mut self) -> Result<Option<Current<T>>, AuctionError> {
+        if let Some(current) = self.current {
+            return Ok(current);
+        }
+        let bids = &self.bids;
+        let AuctionParams { increment, min_bid } = self.params;
+        let found = if let Some(trace) = self.trace.as_mut() {
+            winning_bid(
+                self.ranking.values().map(|&id| (id, &bids[&id].bid)),
+                increment, min_bid, Some(trace)
+            )
+        } else {
+            // Carry on from the highest bid that hasn't changed.
+            let (from, mut scan) = match self.scans.iter().next_back() {
+                Some((&key, &scan)) => (Excluded(key), scan),
+                None => (Unbounded, None)
+            };
+            for (&key, &id) in self.ranking.range((from, Unbounded)) {
+                scan = scan_bid(
+                    scan, id, &bids[&id].bid, increment, min_bid, None
+                );
+                self.scans.insert(key, scan);
+            }
+            scan.map(|scan| {
+                (scan.winner, scan.runner_up, scan.rate, scan.expiry)
+            })
+        };
+        let current = match found {
+            Some((winner, runner_up, rate, expiry)) => {
+                let (end, spent) = find_end(
+                    rate, bids[&winner].bid.expense_limit, expiry, self.now
+                )?;
+                if rate != 0.into() && spent == 0.into() {
+                    return Err(AuctionError::Stalled {
+                        bid: winner, at: self.now
+                    });
+                }
+                Some(Current {
+                    winner,
+                    runner_up: runner_up.map(|id| (id, bids[&id].bid.data)),
+                    rate, end
+                })
+            },
+            None => None
+        };
+        self.current = Some(current);
+        Ok(current)
     }
-    output
 }
 
 #[cfg(test)]
+#[allow(clippy::zero_prefixed_literal, clippy::inconsistent_digit_grouping)]
 mod tests {
     use super::*;
+    use project_brilliant_utilities::{SECONDS_PER_DAY, EPOCH};
 
     macro_rules! assert_almost_eq {
         ($left:expr, $right:expr, within $bound:expr) => ({
@@ -166,107 +812,176 @@ mod tests {
         });
     }
 
+    /// Put `bids`, in the order they were placed, into priority order.
+    fn by_priority<T: Copy>(bids: &[Bid<T>]) -> Vec<(usize, &Bid<T>)> {
+        let mut bids: Vec<_> = bids.iter().enumerate().collect();
+        bids.sort_by(|(i, a), (j, b)| a.bid.cmp(&b.bid).then(j.cmp(i)));
+        bids
+    }
+
     #[test]
     fn winning_bid_first_come() {
-        let mut bids = vec![
+        let bids = [
             Bid {
                 bid:           Currency::from( 5_00),
-                expense_limit: Currency::from(25_00) * SECONDS_PER_DAY,
-                expiry: Second::from(9001),  // that's impossible!
+                expense_limit: Token::from_cents(25_00),
+                expiry: Timestamp::from(9001),  // that's impossible!
                 data: "Winner"
             },
             Bid {
                 bid:           Currency::from( 1_00),
-                expense_limit: Currency::from( 5_00) * SECONDS_PER_DAY,
-                expiry: Second::from(9002),  // more impossible!
+                expense_limit: Token::from_cents(5_00),
+                expiry: Timestamp::from(9002),  // more impossible!
                 data: "No chance"
             },
             Bid {
                 bid:           Currency::from( 5_00),
-                expense_limit: Currency::from(   10) * SECONDS_PER_DAY,
-                expiry: Second::from(9400),  // crazy!
+                expense_limit: Token::from_cents(10),
+                expiry: Timestamp::from(9400),  // crazy!
                 data: "Sadly not"
             }
         ];
-        let (winner, bid, expiry) = winning_bid(
-            bids.iter_mut().collect(),
+        let (winner, runner_up, bid, expiry) = winning_bid(
+            by_priority(&bids),
             Currency::from(                      10),
-            Currency::from(                       0)
+            Currency::from(                       0),
+            None
         ).unwrap();
-        assert_eq!(winner.data, "Winner");
+        assert_eq!(bids[winner].data, "Winner");
+        assert_eq!(bids[runner_up.unwrap()].data, "Sadly not");
         assert_eq!(bid, Currency::from(5_00));
-        assert_eq!(expiry, Second::from(9001));
+        assert_eq!(expiry, Timestamp::from(9001));
     }
     #[test]
     fn winning_bid_no_bid() {
-        assert!(winning_bid::<()>(
-            vec![],
+        assert!(winning_bid(
+            by_priority::<()>(&[]),
             Currency::from(2362),
-            Currency::from(311)
+            Currency::from(311),
+            None
         ).is_none());
     }
     #[test]
     fn winning_bid_one_bid() {
-        let mut bids = vec![
+        let bids = [
             Bid {
                 bid:           Currency::from( 5_00),
-                expense_limit: Currency::from(90_00) * SECONDS_PER_DAY,
-                expiry: Second::from(3),
+                expense_limit: Token::from_cents(90_00),
+                expiry: Timestamp::from(3),
                 data: "Winner"
             }
         ];
-        let (winner, bid, expiry) = winning_bid(
-            bids.iter_mut().collect(),
+        let (winner, runner_up, bid, expiry) = winning_bid(
+            by_priority(&bids),
             Currency::from(                      10),
-            Currency::from(                       0)
+            Currency::from(                       0),
+            None
         ).unwrap();
-        assert_eq!(winner.data, "Winner");
+        assert_eq!(bids[winner].data, "Winner");
+        assert_eq!(runner_up, None);
         assert_eq!(bid, Currency::from(0));
-        assert_eq!(expiry, Second::from(3));
+        assert_eq!(expiry, Timestamp::from(3));
     }
 
     #[test]
-    fn valid_bids_static() {
-        let bids = [
-            Bid {
-                bid:           Currency::from( 0_00),
-                expense_limit: Currency::from( 1_00) * SECONDS_PER_DAY,
-                expiry: SECONDS_PER_DAY + Second::from(500),
-                data: "Invalid"
-            },
-            Bid {
-                bid:           Currency::from(10_00),
-                expense_limit: Token::from(0),
-                expiry: SECONDS_PER_DAY + Second::from(200),
-                data: "Invalid"
-            },
-            Bid {
-                bid:           Currency::from( 4_50),
-                expense_limit: Currency::from(26_00) * SECONDS_PER_DAY,
-                expiry: SECONDS_PER_DAY + Second::from(800),
-                data: "Valid #1"
-            },
-            Bid {
-                bid:           Currency::from(63_00),
-                expense_limit: Currency::from( 2_40) * SECONDS_PER_DAY,
-                expiry: SECONDS_PER_DAY - Second::from(124),
-                data: "Invalid"
-            },
-            Bid {
-                bid:           Currency::from(84_50),
-                expense_limit: Currency::from(68_20) * SECONDS_PER_DAY,
-                expiry: SECONDS_PER_DAY + Second::from(620),
-                data: "Valid #2"
-            }
-        ];
-        #[allow(deprecated)]
-        let output = valid_bids(&bids, SECONDS_PER_DAY);
+    fn validate_bid_static() {
+        let params = AuctionParams {
+            increment: Currency::from(                10),
+            min_bid:   Currency::from(              1_00)
+        };
+        let bid = |bid, expense_limit, expiry| Bid {
+            bid, expense_limit, expiry, data: ()
+        };
+        let check = |bid| validate_bid(&bid, &params, EPOCH + SECONDS_PER_DAY);
+
+        assert_eq!(check(bid(
+            Currency::from( 0_00),
+            Token::from_cents(1_00),
+            EPOCH + SECONDS_PER_DAY + Second::from(500)
+        )), Err(BidRejection::ZeroBid));
+        assert_eq!(check(bid(
+            Currency::from(   99),
+            Token::from_cents(1_00),
+            EPOCH + SECONDS_PER_DAY + Second::from(500)
+        )), Err(BidRejection::BelowMinimum {
+            min_bid: Currency::from(1_00)
+        }));
+        assert_eq!(check(bid(
+            Currency::from(10_00),
+            Token::from(0),
+            EPOCH + SECONDS_PER_DAY + Second::from(200)
+        )), Err(BidRejection::CantCoverOneSecond {
+            needed: Token::from(1_00),
+            expense_limit: Token::from(0)
+        }));
+        assert_eq!(check(bid(
+            Currency::from(10_00),
+            Token::from(99),
+            EPOCH + SECONDS_PER_DAY + Second::from(200)
+        )), Err(BidRejection::CantCoverOneSecond {
+            needed: Token::from(1_00),
+            expense_limit: Token::from(99)
+        }));
+        assert_eq!(check(bid(
+            Currency::from(63_00),
+            Token::from_cents(2_40),
+            EPOCH + SECONDS_PER_DAY - Second::from(124)
+        )), Err(BidRejection::ExpiryInPast {
+            expiry: EPOCH + SECONDS_PER_DAY - Second::from(124),
+            now: EPOCH + SECONDS_PER_DAY
+        }));
+        assert_eq!(check(bid(
+            Currency::from( 4_50),
+            Token::from_cents(26_00),
+            EPOCH + SECONDS_PER_DAY + Second::from(800)
+        )), Ok(()));
+        assert_eq!(check(bid(
+            Currency::from( 1_00),
+            Token::from(1_00),
+            EPOCH + SECONDS_PER_DAY + Second::from(1)
+        )), Ok(()));
+    }
+
+    #[test]
+    fn bid_rejection_display() {
         assert_eq!(
-            output.into_iter().map(|bid| bid.data).collect::<Vec<&str>>(),
-            vec!["Valid #1", "Valid #2"]
+            BidRejection::BelowMinimum {
+                min_bid: Currency::from(4_60)
+            }.to_string(),
+            "the bid is below the ad box's minimum bid of $4.60/day"
         );
     }
 
+    #[test]
+    fn validate_bid_overflow() {
+        let params = AuctionParams {
+            increment: Currency::from(                10),
+            min_bid:   Currency::from(                 0)
+        };
+        let bid = |bid, expense_limit, expiry| Bid {
+            bid, expense_limit, expiry, data: ()
+        };
+        let check = |bid| validate_bid(&bid, &params, EPOCH);
+
+        // Nothing could outbid it.
+        assert_eq!(check(bid(
+            Currency::max_value(),
+            Token::from(1_00),
+            EPOCH + SECONDS_PER_DAY
+        )), Err(BidRejection::OverflowRisk));
+        assert_eq!(check(bid(
+            Currency::max_value() - Currency::from(10),
+            Token::from(1_00),
+            EPOCH + SECONDS_PER_DAY
+        )), Ok(()));
+        // It could never spend this much, so it doesn't matter.
+        assert_eq!(check(bid(
+            Currency::from(1 << 32),
+            Token::max_value(),
+            Timestamp::max_value()
+        )), Ok(()));
+    }
+
     #[test]
     fn find_end_valid() {
         for amount in (0..1_000).step_by(73) {
@@ -276,10 +991,10 @@ mod tests {
                     .step_by(5_647_289_173_652) {
                         let amount = Currency::from(amount);
                         let limit = Token::from(limit);
-                        let now = Second::from(now);
-                        let expiry = Second::from(expiry);
+                        let now = Timestamp::from(now);
+                        let expiry = Timestamp::from(expiry);
                         let (finish, spent) = find_end(amount, limit,
-                                                       expiry, now);
+                                                       expiry, now).unwrap();
                         // Sanity
                         assert!(finish <= expiry,
                             "{:?} > {:?}", finish, expiry);
@@ -320,6 +1035,167 @@ mod tests {
         }
     }
 
+    #[test]
+    fn find_end_expired() {
+        assert_eq!(
+            find_end(Currency::from(1_00), Token::from(1_00),
+                     Timestamp::from(10), Timestamp::from(10)),
+            Err(AuctionError::ExpiryInPast {
+                expiry: Timestamp::from(10), now: Timestamp::from(10)
+            })
+        );
+        assert_eq!(
+            find_end(Currency::from(0), Token::from(0),
+                     EPOCH, Timestamp::max_value()),
+            Err(AuctionError::ExpiryInPast {
+                expiry: Timestamp::from(0), now: Timestamp::max_value()
+            })
+        );
+    }
+
+    #[test]
+    fn find_end_huge() {
+        assert_eq!(
+            find_end(Currency::from(1), Token::max_value(),
+                     Timestamp::max_value(), Timestamp::from(1)),
+            Ok((Timestamp::max_value(), Token::max_value() - Token::from(1)))
+        );
+        assert_eq!(
+            find_end(Currency::from(2), Token::max_value(),
+                     Timestamp::max_value(),
+                     Timestamp::max_value() - Second::from(10)),
+            Ok((Timestamp::max_value(), Token::from(20)))
+        );
+        assert_eq!(
+            find_end(Currency::max_value(), Token::max_value(),
+                     Timestamp::max_value(), EPOCH),
+            Ok((Timestamp::from(1), Token::max_value()))
+        );
+    }
+
+    /// Bids that'd bring the whole server down, if they were let in.
+    #[test]
+    fn run_auction_adversarial() {
+        let bids = vec![
+            Bid {
+                bid:           Currency::max_value(),
+                expense_limit: Token::max_value(),
+                expiry: Timestamp::max_value(),
+                data: "Everything"
+            },
+            Bid {
+                bid:           Currency::from(1),
+                expense_limit: Token::max_value(),
+                expiry: Timestamp::max_value(),
+                data: "Forever"
+            },
+            Bid {
+                bid:           Currency::from(0),
+                expense_limit: Token::max_value(),
+                expiry: Timestamp::max_value(),
+                data: "Nothing"
+            },
+            Bid {
+                bid:           Currency::from(5_00),
+                expense_limit: Token::from(1_00_00),
+                expiry: Timestamp::from(0),
+                data: "Already expired"
+            },
+            Bid {
+                bid:           Currency::from(2),
+                expense_limit: Token::from(10),
+                expiry: Timestamp::max_value(),
+                data: "Brief"
+            }
+        ];
+        let trace = run_auction_traced(
+            bids,
+            Currency::from(                        1),
+            Currency::from(                        0),
+            Timestamp::from(1_000)
+        ).unwrap();
+        assert_eq!(
+            trace.dropped.iter().map(|decision| match decision {
+                Decision::Rejected { bid, reason } => (*bid, *reason),
+                _ => panic!("{:?} wasn't rejected", decision)
+            }).collect::<Vec<_>>(),
+            vec![
+                ("Everything", BidRejection::OverflowRisk),
+                ("Nothing", BidRejection::ZeroBid),
+                ("Already expired", BidRejection::ExpiryInPast {
+                    expiry: Timestamp::from(0), now: Timestamp::from(1_000)
+                })
+            ]
+        );
+        assert_eq!(
+            trace.segments.iter().map(|x| x.segment).collect::<Vec<_>>(),
+            vec![
+                Segment {
+                    start: Timestamp::from(1_000),
+                    end: Timestamp::from(1_005),
+                    winner: "Brief",
+                    rate: Currency::from(2),
+                    spent: Token::from(10),
+                    runner_up: Some("Forever")
+                },
+                Segment {
+                    start: Timestamp::from(1_005),
+                    end: Timestamp::max_value(),
+                    winner: "Forever",
+                    rate: Currency::from(0),
+                    spent: Token::from(0),
+                    runner_up: None
+                }
+            ]
+        );
+    }
+
+    /// Bids as big as they come, that never expire, still give the same
+    /// answer every time.
+    #[test]
+    fn run_auction_max_expiries() {
+        let bids = vec![
+            Bid {
+                bid:           Currency::from(1 << 62),
+                expense_limit: Token::max_value(),
+                expiry: Timestamp::max_value(),
+                data: "Big"
+            },
+            Bid {
+                bid:           Currency::from(1 << 61),
+                expense_limit: Token::max_value(),
+                expiry: Timestamp::max_value(),
+                data: "Not so big"
+            }
+        ];
+        let auction = run_auction(
+            bids,
+            Currency::from(                        1),
+            Currency::from(                        0),
+            EPOCH
+        ).unwrap();
+        assert_eq!(auction, vec![
+            // Big can only pay for 7 seconds at this price...
+            Segment {
+                start: Timestamp::from(0),
+                end: Timestamp::from(7),
+                winner: "Big",
+                rate: Currency::from((1 << 61) + 1),
+                spent: Token::from(7 * ((1 << 61) + 1)),
+                runner_up: Some("Not so big")
+            },
+            // ...and then can't afford to outbid Not so big again.
+            Segment {
+                start: Timestamp::from(7),
+                end: Timestamp::max_value(),
+                winner: "Not so big",
+                rate: Currency::from(0),
+                spent: Token::from(0),
+                runner_up: None
+            }
+        ]);
+    }
+
     /// Alice starts out and bids a maximum of $5 a day for a week.
     /// Since she's the only bidder, her bid starts out at $0.
     /// Free advertising!
@@ -330,7 +1206,7 @@ mod tests {
                 bid:           Currency::from(  5_00),  // $5
                 expense_limit: Token::from(42),  // unspecified (should default
                                                  // to 500 * 7 * N_PER_DAY)
-                expiry: 7 * SECONDS_PER_DAY,
+                expiry: EPOCH + 7 * SECONDS_PER_DAY,
                 data: "Alice"
             }
         ];
@@ -338,15 +1214,17 @@ mod tests {
             bids,
             Currency::from(                     0_10),  // 10¢
             Currency::from(                     0_00),
-            Second::from(0)                             // t=0
-        );
+            EPOCH                             // t=0
+        ).unwrap();
         assert_eq!(auction.len(), 1);
 
         // Alice starts out…
-        let (alice, expiry, spent) = auction[0];
+        let Segment { winner: alice, end: expiry, spent, .. } = auction[0];
         assert_eq!(alice, "Alice");
-        assert_eq!(expiry, 7 * SECONDS_PER_DAY);
+        assert_eq!(expiry, EPOCH + 7 * SECONDS_PER_DAY);
         assert_eq!(spent, Token::from(0));
+        assert_eq!(auction[0].rate, Currency::from(0));
+        assert_eq!(auction[0].runner_up, None);
     }
 
     /// Partario wants in! He sees Alice's bid of $0 and decides
@@ -361,16 +1239,15 @@ mod tests {
         let bids = vec![
             Bid {
                 bid:           Currency::from(  5_00),  // $5
-                expense_limit: Currency::from(  1_10)   // $1.10
-                             * SECONDS_PER_DAY
+                expense_limit: Token::from_cents(1_10)  // $1.10
                              + Token::from(42),
-                expiry: 7 * SECONDS_PER_DAY,
+                expiry: EPOCH + 7 * SECONDS_PER_DAY,
                 data: "Alice"
             },
             Bid {
                 bid:           Currency::from(  1_00),  // $1
                 expense_limit: Token::from(42),  // 42 == unspecified
-                expiry: 1 * SECONDS_PER_DAY,
+                expiry: EPOCH + 1 * SECONDS_PER_DAY,
                 data: "Partario"
             }
         ];
@@ -378,21 +1255,30 @@ mod tests {
             bids,
             Currency::from(                       10),  // 10¢
             Currency::from(                        0),
-            Second::from(0)                             // t=0
-        );
+            EPOCH                             // t=0
+        ).unwrap();
         assert_eq!(auction.len(), 2);
 
         // Partario wants in!…
-        let (alice, expiry, spent) = auction[0];
-        assert_eq!(alice, "Alice");
-        assert_eq!(expiry, 1 * SECONDS_PER_DAY);
-        assert_eq!(spent, Currency::from(       1_10) * SECONDS_PER_DAY);
+        assert_eq!(
+            auction[0],
+            Segment {
+                start: Timestamp::from(0),
+                end: EPOCH + 1 * SECONDS_PER_DAY,
+                winner: "Alice",
+                rate: Currency::from(                   1_10),
+                spent: Currency::from(       1_10) * SECONDS_PER_DAY,
+                runner_up: Some("Partario")
+            }
+        );
 
         // A day later…
-        let (alice, expiry, spent) = auction[1];
+        let Segment { winner: alice, end: expiry, spent, .. } = auction[1];
         assert_eq!(alice, "Alice");
-        assert_eq!(expiry, 7 * SECONDS_PER_DAY);
+        assert_eq!(expiry, EPOCH + 7 * SECONDS_PER_DAY);
         assert_eq!(spent, Token::from(0));
+        assert_eq!(auction[1].start, EPOCH + 1 * SECONDS_PER_DAY);
+        assert_eq!(auction[1].runner_up, None);
     }
 
     /// Partario's back, and this time he decides to bid $100 a day
@@ -414,14 +1300,13 @@ mod tests {
             Bid {
                 bid:           Currency::from(  5_00),  // $5
                 expense_limit: Token::from(42),
-                expiry: 7 * SECONDS_PER_DAY,
+                expiry: EPOCH + 7 * SECONDS_PER_DAY,
                 data: "Alice"
             },
             Bid {
                 bid:           Currency::from(100_00),  // $100
-                expense_limit: Currency::from(  1_00)   // $1
-                             * SECONDS_PER_DAY,
-                expiry: 1 * SECONDS_PER_DAY,
+                expense_limit: Token::from_cents(1_00),  // $1
+                expiry: EPOCH + 1 * SECONDS_PER_DAY,
                 data: "Partario"
             }
         ];
@@ -429,23 +1314,24 @@ mod tests {
             bids,
             Currency::from(                       10),  // 10¢
             Currency::from(                        0),
-            Second::from(0)
-        );
+            EPOCH
+        ).unwrap();
         assert_eq!(auction.len(), 2);
 
         // Since Partario's bid…
-        let (partario, expiry, spent) = auction[0];
+        let Segment { winner: partario, end: expiry, spent, .. } = auction[0];
         assert_eq!(partario, "Partario");
-        assert_almost_eq!(expiry, SECONDS_PER_DAY / 24 * 5,
+        assert_almost_eq!(expiry - EPOCH, SECONDS_PER_DAY / 24 * 5,
             within SECONDS_PER_DAY / 24);
-        assert_almost_eq!(spent, Currency::from(1_00)   // $1
-                               * SECONDS_PER_DAY,
+        assert_almost_eq!(spent, Token::from_cents(1_00),  // $1
             within Token::from(10_000));
+        assert_eq!(auction[0].rate, Currency::from(5_10));
+        assert_eq!(auction[0].runner_up, Some("Alice"));
 
         // His bid expires…
-        let (alice, expiry, spent) = auction[1];
+        let Segment { winner: alice, end: expiry, spent, .. } = auction[1];
         assert_eq!(alice, "Alice");
-        assert_eq!(expiry, 7 * SECONDS_PER_DAY);
+        assert_eq!(expiry, EPOCH + 7 * SECONDS_PER_DAY);
         assert_eq!(spent, Token::from(0));
     }
 
@@ -468,23 +1354,21 @@ mod tests {
         let bids = vec![
             Bid {
                 bid:           Currency::from(  5_00),  // $5
-                expense_limit: Currency::from(  5_00)
-                             * 7 * SECONDS_PER_DAY
+                expense_limit: Token::from_cents(7 * 5_00)
                              + Token::from(42),
-                expiry: 7 * SECONDS_PER_DAY,
+                expiry: EPOCH + 7 * SECONDS_PER_DAY,
                 data: "Alice"
             },
             Bid {
                 bid:           Currency::from(100_00),  // $100
-                expense_limit: Currency::from(  1_00)   // $1
-                             * SECONDS_PER_DAY,
-                expiry: 8 * SECONDS_PER_DAY,  // started at 1d
+                expense_limit: Token::from_cents(1_00),  // $1
+                expiry: EPOCH + 8 * SECONDS_PER_DAY,  // started at 1d
                 data: "Partario"
             },
             Bid {
                 bid:           Currency::from(  1_00),  // $1
                 expense_limit: Token::from(42),
-                expiry: 8 * SECONDS_PER_DAY,
+                expiry: EPOCH + 8 * SECONDS_PER_DAY,
                 data: "Partario2"
             }
         ];
@@ -492,34 +1376,43 @@ mod tests {
             bids,
             Currency::from(                       10),  // 10¢
             Currency::from(                        0),
-            SECONDS_PER_DAY                             // t=1d
-        );
+            EPOCH + SECONDS_PER_DAY                             // t=1d
+        ).unwrap();
         assert_eq!(auction.len(), 3);
 
         // Partario also decides…
-        let (partario, expiry, spent) = auction[0];
+        let Segment { winner: partario, end: expiry, spent, .. } = auction[0];
         assert_eq!(partario, "Partario");
-        assert_almost_eq!(expiry, SECONDS_PER_DAY / 24 * (24 + 5),
+        assert_almost_eq!(expiry - EPOCH, SECONDS_PER_DAY / 24 * (24 + 5),
             within SECONDS_PER_DAY / 24);
-        assert_almost_eq!(spent, Currency::from(1_00)   // $1
-                               * SECONDS_PER_DAY,
+        assert_almost_eq!(spent, Token::from_cents(1_00),  // $1
             within Token::from(10_000));
 
         // Since Partario's bid…
         assert_eq!(
             auction[1],
-            (
-                "Alice",
-                7 * SECONDS_PER_DAY,
-                (7 * SECONDS_PER_DAY - expiry)
-                               * Currency::from(1_10)   // $1
-            )
+            Segment {
+                start: expiry,
+                end: EPOCH + 7 * SECONDS_PER_DAY,
+                winner: "Alice",
+                rate: Currency::from(1_10),
+                spent: (EPOCH + 7 * SECONDS_PER_DAY - expiry)
+                                 * Currency::from(1_10),  // $1.10
+                runner_up: Some("Partario2")
+            }
         );
 
         // Finally, a week…
         assert_eq!(
             auction[2],
-            ("Partario2", 8 * SECONDS_PER_DAY, Token::from(0))
+            Segment {
+                start: EPOCH + 7 * SECONDS_PER_DAY,
+                end: EPOCH + 8 * SECONDS_PER_DAY,
+                winner: "Partario2",
+                rate: Currency::from(0),
+                spent: Token::from(0),
+                runner_up: None
+            }
         );
     }
 
@@ -529,13 +1422,13 @@ mod tests {
             Bid {
                 bid:           Currency::from(  5_00),
                 expense_limit: Token::from(     4_00),
-                expiry: Second::from(10_000),
+                expiry: Timestamp::from(10_000),
                 data: "Can't pay."
             },
             Bid {
                 bid:           Currency::from(  5_00),
                 expense_limit: Token::from(     6_00),
-                expiry: Second::from(10_000),
+                expiry: Timestamp::from(10_000),
                 data: "Can only pay once."
             }
         ];
@@ -543,13 +1436,644 @@ mod tests {
             bids,
             Currency::from(                       10),
             Currency::from(                     4_60),
-            Second::from(0)
-        );
+            EPOCH
+        ).unwrap();
         assert_eq!(auction.len(), 1);
 
         assert_eq!(
             auction[0],
-            ("Can only pay once.", Second::from(1), Token::from(460))
+            Segment {
+                start: Timestamp::from(0),
+                end: Timestamp::from(1),
+                winner: "Can only pay once.",
+                rate: Currency::from(4_60),
+                spent: Token::from(460),
+                runner_up: None
+            }
+        );
+    }
+
+    fn revenge_2_bids() -> Vec<Bid<&'static str>> {
+        vec![
+            Bid {
+                bid:           Currency::from(  5_00),  // $5
+                expense_limit: Token::from_cents(7 * 5_00)
+                             + Token::from(42),
+                expiry: EPOCH + 7 * SECONDS_PER_DAY,
+                data: "Alice"
+            },
+            Bid {
+                bid:           Currency::from(100_00),  // $100
+                expense_limit: Token::from_cents(1_00),  // $1
+                expiry: EPOCH + 8 * SECONDS_PER_DAY,
+                data: "Partario"
+            },
+            Bid {
+                bid:           Currency::from(  1_00),  // $1
+                expense_limit: Token::from(42),
+                expiry: EPOCH + 8 * SECONDS_PER_DAY,
+                data: "Partario2"
+            }
+        ]
+    }
+
+    #[test]
+    fn auction_state_matches_run_auction() {
+        let mut state = AuctionState::new(
+            Currency::from(                       10),
+            Currency::from(                        0),
+            EPOCH + SECONDS_PER_DAY
+        );
+        for (id, bid) in revenge_2_bids().into_iter().enumerate() {
+            state.place_bid(BidId(id as u64), bid);
+        }
+        let (_, partario) = state.current_winner().unwrap().unwrap();
+        assert_eq!(partario.data, "Partario");
+        assert_eq!(
+            state.advance_to(Timestamp::max_value()).unwrap(),
+            run_auction(
+                revenge_2_bids(),
+                Currency::from(                   10),
+                Currency::from(                    0),
+                EPOCH + SECONDS_PER_DAY
+            ).unwrap()
+        );
+        assert!(state.current_winner().unwrap().is_none());
+    }
+
+    /// Stopping to look every hour doesn't change who gets shown, or how
+    /// much they pay.
+    #[test]
+    fn auction_state_advance_in_steps() {
+        let mut state = AuctionState::new(
+            Currency::from(                       10),
+            Currency::from(                        0),
+            EPOCH + SECONDS_PER_DAY
+        );
+        for (id, bid) in revenge_2_bids().into_iter().enumerate() {
+            state.place_bid(BidId(id as u64), bid);
+        }
+        let mut pieces = Vec::new();
+        let mut now = EPOCH + SECONDS_PER_DAY;
+        while now < EPOCH + 9 * SECONDS_PER_DAY {
+            now += SECONDS_PER_DAY / 24;
+            pieces.extend(state.advance_to(now).unwrap());
+        }
+        assert_eq!(state.now(), now);
+
+        // Glue the pieces back together.
+        let mut glued: Vec<Segment<&str>> = Vec::new();
+        for piece in pieces {
+            match glued.last_mut() {
+                Some(last) if last.winner == piece.winner => {
+                    assert_eq!(last.end, piece.start);
+                    assert_eq!(last.rate, piece.rate);
+                    last.end = piece.end;
+                    last.spent += piece.spent;
+                },
+                _ => glued.push(piece)
+            }
+        }
+        assert_eq!(
+            glued,
+            run_auction(
+                revenge_2_bids(),
+                Currency::from(                   10),
+                Currency::from(                    0),
+                EPOCH + SECONDS_PER_DAY
+            ).unwrap()
+        );
+    }
+
+    /// Waking up at the end of every segment sees the same segments.
+    #[test]
+    fn auction_state_segment_end() {
+        let mut state = AuctionState::new(
+            Currency::from(                       10),
+            Currency::from(                        0),
+            EPOCH + SECONDS_PER_DAY
+        );
+        for (id, bid) in revenge_2_bids().into_iter().enumerate() {
+            state.place_bid(BidId(id as u64), bid);
+        }
+        let mut segments = Vec::new();
+        while let Some(end) = state.segment_end().unwrap() {
+            let shown = state.advance_to(end).unwrap();
+            assert_eq!(shown.len(), 1);
+            assert_eq!(shown[0].end, end);
+            segments.extend(shown);
+        }
+        assert!(state.current_winner().unwrap().is_none());
+        assert_eq!(
+            segments,
+            run_auction(
+                revenge_2_bids(),
+                Currency::from(                   10),
+                Currency::from(                    0),
+                EPOCH + SECONDS_PER_DAY
+            ).unwrap()
+        );
+    }
+
+    /// Going through only the bids above a change gives the same timeline
+    /// as going through every bid every time, which tracing does.
+    #[test]
+    fn auction_state_scans_match_full_scans() {
+        let mut timelines = [Vec::new(), Vec::new()];
+        for (traced, timeline) in timelines.iter_mut().enumerate() {
+            let mut state = AuctionState::new(
+                Currency::from(                   10),
+                Currency::from(                    0),
+                EPOCH + SECONDS_PER_DAY
+            );
+            if traced == 1 {
+                state.trace = Some(Vec::new());
+            }
+            for (id, bid) in revenge_2_bids().into_iter().enumerate() {
+                state.place_bid(BidId(id as u64), bid);
+            }
+            let mut now = EPOCH + SECONDS_PER_DAY;
+            for hour in 0..9 * 24 {
+                now += SECONDS_PER_DAY / 24;
+                timeline.extend(state.advance_to(now).unwrap());
+                match hour {
+                    3 => {
+                        state.cancel_bid(BidId(1));
+                    },
+                    5 => {
+                        state.amend_bid(
+                            BidId(0), Amendment::Bid(Currency::from(2_00))
+                        );
+                    },
+                    8 => {
+                        state.place_bid(BidId(3), Bid {
+                            bid:           Currency::from(3_00),
+                            expense_limit: Token::from_cents(50),
+                            expiry: EPOCH + 5 * SECONDS_PER_DAY,
+                            data: "Latecomer"
+                        });
+                    },
+                    _ => ()
+                }
+            }
+        }
+        assert!(timelines[0].len() > 3);
+        assert_eq!(timelines[0], timelines[1]);
+    }
+
+    /// Partario thinks better of his $100 bid an hour after placing it,
+    /// and cancels it. Alice is back on top, for free.
+    #[test]
+    fn auction_state_cancel_bid() {
+        let mut state = AuctionState::new(
+            Currency::from(                       10),
+            Currency::from(                        0),
+            EPOCH
+        );
+        state.place_bid(BidId(0), Bid {
+            bid:           Currency::from(  5_00),  // $5
+            expense_limit: Token::from(42),
+            expiry: EPOCH + 7 * SECONDS_PER_DAY,
+            data: "Alice"
+        });
+        state.place_bid(BidId(1), Bid {
+            bid:           Currency::from(100_00),  // $100
+            expense_limit: Token::from_cents(1_00),  // $1
+            expiry: EPOCH + 1 * SECONDS_PER_DAY,
+            data: "Partario"
+        });
+        let hour = SECONDS_PER_DAY / 24;
+        assert_eq!(
+            state.advance_to(EPOCH + hour).unwrap(),
+            vec![Segment {
+                start: Timestamp::from(0),
+                end: EPOCH + hour,
+                winner: "Partario",
+                rate: Currency::from(5_10),
+                spent: Currency::from(5_10) * hour,
+                runner_up: Some("Alice")
+            }]
+        );
+
+        let cancelled = state.cancel_bid(BidId(1)).unwrap();
+        assert_eq!(cancelled.expense_limit,
+                   Token::from_cents(1_00) - Currency::from(5_10) * hour);
+        assert!(state.cancel_bid(BidId(1)).is_none());
+        let (id, alice) = state.current_winner().unwrap().unwrap();
+        assert_eq!((id, alice.data), (BidId(0), "Alice"));
+
+        assert_eq!(
+            state.advance_to(Timestamp::max_value()).unwrap(),
+            vec![Segment {
+                start: EPOCH + hour,
+                end: EPOCH + 7 * SECONDS_PER_DAY,
+                winner: "Alice",
+                rate: Currency::from(0),
+                spent: Token::from(0),
+                runner_up: None
+            }]
+        );
+    }
+
+    /// Alice has had free advertising for a day when Partario bids $1 a
+    /// day. From then on, Alice pays $1.10 a day.
+    #[test]
+    fn auction_state_place_bid_later() {
+        let mut state = AuctionState::new(
+            Currency::from(                       10),
+            Currency::from(                        0),
+            EPOCH
+        );
+        state.place_bid(BidId(0), Bid {
+            bid:           Currency::from(  5_00),  // $5
+            expense_limit: Token::from_cents(7 * 5_00),
+            expiry: EPOCH + 7 * SECONDS_PER_DAY,
+            data: "Alice"
+        });
+        assert_eq!(
+            state.advance_to(EPOCH + SECONDS_PER_DAY).unwrap(),
+            vec![Segment {
+                start: Timestamp::from(0),
+                end: EPOCH + SECONDS_PER_DAY,
+                winner: "Alice",
+                rate: Currency::from(0),
+                spent: Token::from(0),
+                runner_up: None
+            }]
+        );
+
+        state.place_bid(BidId(1), Bid {
+            bid:           Currency::from(  1_00),  // $1
+            expense_limit: Token::from(42),
+            expiry: EPOCH + 2 * SECONDS_PER_DAY,
+            data: "Partario"
+        });
+        assert_eq!(
+            state.advance_to(Timestamp::max_value()).unwrap(),
+            vec![
+                Segment {
+                    start: EPOCH + SECONDS_PER_DAY,
+                    end: EPOCH + 2 * SECONDS_PER_DAY,
+                    winner: "Alice",
+                    rate: Currency::from(1_10),
+                    spent: Currency::from(1_10) * SECONDS_PER_DAY,
+                    runner_up: Some("Partario")
+                },
+                Segment {
+                    start: EPOCH + 2 * SECONDS_PER_DAY,
+                    end: EPOCH + 7 * SECONDS_PER_DAY,
+                    winner: "Alice",
+                    rate: Currency::from(0),
+                    spent: Token::from(0),
+                    runner_up: None
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn replay_auction_no_changes() {
+        let events = revenge_2_bids().into_iter().enumerate().map(
+            |(id, bid)| (
+                EPOCH + SECONDS_PER_DAY, BidId(id as u64), BidEvent::Place(bid)
+            )
+        );
+        assert_eq!(
+            replay_auction(
+                events,
+                Currency::from(                   10),
+                Currency::from(                    0),
+                EPOCH + SECONDS_PER_DAY
+            ).unwrap(),
+            run_auction(
+                revenge_2_bids(),
+                Currency::from(                   10),
+                Currency::from(                    0),
+                EPOCH + SECONDS_PER_DAY
+            ).unwrap()
+        );
+    }
+
+    /// An hour after outbidding Alice, Partario realises he's paying more
+    /// than he'd like and lowers his bid to $3 a day. What he's paid so far
+    /// stays paid, but now Alice outbids him, at $3.10 a day, until his bid
+    /// expires.
+    #[test]
+    fn replay_auction_partario_lowers_bid() {
+        let hour = SECONDS_PER_DAY / 24;
+        let events = vec![
+            (EPOCH, BidId(0), BidEvent::Place(Bid {
+                bid:           Currency::from(  5_00),  // $5
+                expense_limit: Token::from_cents(7 * 5_00),
+                expiry: EPOCH + 7 * SECONDS_PER_DAY,
+                data: "Alice"
+            })),
+            (EPOCH, BidId(1), BidEvent::Place(Bid {
+                bid:           Currency::from(100_00),  // $100
+                expense_limit: Token::from_cents(1_00),  // $1
+                expiry: EPOCH + 1 * SECONDS_PER_DAY,
+                data: "Partario"
+            })),
+            (EPOCH + hour, BidId(1), BidEvent::Amend(
+                Amendment::Bid(Currency::from(3_00))
+            ))
+        ];
+        assert_eq!(
+            replay_auction(
+                events,
+                Currency::from(                   10),  // 10¢
+                Currency::from(                    0),
+                EPOCH
+            ).unwrap(),
+            vec![
+                Segment {
+                    start: Timestamp::from(0),
+                    end: EPOCH + hour,
+                    winner: "Partario",
+                    rate: Currency::from(5_10),
+                    spent: Currency::from(5_10) * hour,
+                    runner_up: Some("Alice")
+                },
+                Segment {
+                    start: EPOCH + hour,
+                    end: EPOCH + SECONDS_PER_DAY,
+                    winner: "Alice",
+                    rate: Currency::from(3_10),
+                    spent: Currency::from(3_10) * (SECONDS_PER_DAY - hour),
+                    runner_up: Some("Partario")
+                },
+                Segment {
+                    start: EPOCH + SECONDS_PER_DAY,
+                    end: EPOCH + 7 * SECONDS_PER_DAY,
+                    winner: "Alice",
+                    rate: Currency::from(0),
+                    spent: Token::from(0),
+                    runner_up: None
+                }
+            ]
+        );
+    }
+
+    /// An hour in, Partario raises his expense limit to $2 in total, so he
+    /// stays up for about twice as long. Nothing else changed, so that's
+    /// still one segment. Then it's back to Alice, for free.
+    ///
+    /// At midday, Alice sets her expense limit to nothing, which is as good
+    /// as cancelling. Partario's bid has a few tokens left, and nobody's
+    /// competing, so he gets free advertising until it expires.
+    #[test]
+    fn replay_auction_expense_limits() {
+        let hour = SECONDS_PER_DAY / 24;
+        let events = vec![
+            (EPOCH, BidId(0), BidEvent::Place(Bid {
+                bid:           Currency::from(  5_00),  // $5
+                expense_limit: Token::from_cents(7 * 5_00),
+                expiry: EPOCH + 7 * SECONDS_PER_DAY,
+                data: "Alice"
+            })),
+            (EPOCH, BidId(1), BidEvent::Place(Bid {
+                bid:           Currency::from(100_00),  // $100
+                expense_limit: Token::from_cents(1_00),  // $1
+                expiry: EPOCH + 1 * SECONDS_PER_DAY,
+                data: "Partario"
+            })),
+            (EPOCH + hour, BidId(1), BidEvent::Amend(
+                Amendment::ExpenseLimit(Token::from_cents(2_00))  // $2
+            )),
+            (EPOCH + SECONDS_PER_DAY / 2, BidId(0), BidEvent::Amend(
+                Amendment::ExpenseLimit(Token::from(0))
+            ))
+        ];
+        let auction = replay_auction(
+            events,
+            Currency::from(                       10),  // 10¢
+            Currency::from(                        0),
+            EPOCH
+        ).unwrap();
+        assert_eq!(auction.len(), 3);
+
+        let Segment { winner: partario, end: expiry, spent, .. } = auction[0];
+        assert_eq!(partario, "Partario");
+        assert_almost_eq!(expiry - EPOCH, SECONDS_PER_DAY / 24 * 9,
+            within SECONDS_PER_DAY / 24);
+        assert_almost_eq!(spent, Token::from_cents(2_00),  // $2
+            within Token::from(10_000));
+        assert_eq!(spent, Currency::from(5_10) * (expiry - EPOCH));
+
+        assert_eq!(
+            auction[1],
+            Segment {
+                start: expiry,
+                end: EPOCH + SECONDS_PER_DAY / 2,
+                winner: "Alice",
+                rate: Currency::from(0),
+                spent: Token::from(0),
+                runner_up: None
+            }
+        );
+        assert_eq!(
+            auction[2],
+            Segment {
+                start: EPOCH + SECONDS_PER_DAY / 2,
+                end: EPOCH + SECONDS_PER_DAY,
+                winner: "Partario",
+                rate: Currency::from(0),
+                spent: Token::from(0),
+                runner_up: None
+            }
+        );
+    }
+
+    #[test]
+    fn auction_state_amend_bid() {
+        let mut state = AuctionState::new(
+            Currency::from(                       10),
+            Currency::from(                        0),
+            EPOCH
+        );
+        assert!(state.amend_bid(
+            BidId(0), Amendment::Bid(Currency::from(1))
+        ).is_none());
+        for (id, bid) in revenge_2_bids().into_iter().enumerate() {
+            state.place_bid(BidId(id as u64), bid);
+        }
+        state.advance_to(Timestamp::from(10)).unwrap();
+        assert_eq!(state.spent(BidId(1)), Some(Currency::from(5_10)
+                                             * Second::from(10)));
+        let old = state.amend_bid(
+            BidId(1), Amendment::Bid(Currency::from(6_00))
+        ).unwrap();
+        assert_eq!(old.bid, Currency::from(100_00));
+        assert_eq!(state.get_bid(BidId(1)).unwrap().bid,
+                   Currency::from(6_00));
+        assert_eq!(state.spent(BidId(1)), Some(Currency::from(5_10)
+                                             * Second::from(10)));
+
+        state.amend_bid(BidId(1), Amendment::ExpenseLimit(0.into()));
+        assert!(state.get_bid(BidId(1)).is_none());
+        let (_, alice) = state.current_winner().unwrap().unwrap();
+        assert_eq!(alice.data, "Alice");
+    }
+
+    /// Partario's revenge, again, but this time Alice wants to know why
+    /// she wasn't shown.
+    #[test]
+    fn run_auction_traced_partarios_revenge() {
+        let bids = vec![
+            Bid {
+                bid:           Currency::from(  5_00),  // $5
+                expense_limit: Token::from(42),
+                expiry: EPOCH + 7 * SECONDS_PER_DAY,
+                data: "Alice"
+            },
+            Bid {
+                bid:           Currency::from(100_00),  // $100
+                expense_limit: Token::from_cents(1_00),  // $1
+                expiry: EPOCH + 1 * SECONDS_PER_DAY,
+                data: "Partario"
+            }
+        ];
+        let trace = run_auction_traced(
+            bids.clone(),
+            Currency::from(                       10),  // 10¢
+            Currency::from(                        0),
+            EPOCH
+        ).unwrap();
+        assert_eq!(
+            trace.segments.iter().map(|x| x.segment).collect::<Vec<_>>(),
+            run_auction(
+                bids,
+                Currency::from(                   10),
+                Currency::from(                    0),
+                EPOCH
+            ).unwrap()
+        );
+        assert!(trace.dropped.is_empty());
+        assert_eq!(trace.segments.len(), 2);
+
+        // Partario outbids Alice.
+        assert_eq!(trace.segments[0].decisions, vec![
+            Decision::Outbid {
+                bid: "Alice", outbid: None, rate: Currency::from(0)
+            },
+            Decision::Outbid {
+                bid: "Partario", outbid: Some("Alice"),
+                rate: Currency::from(5_10)
+            }
+        ]);
+
+        // He can't afford to any more, though he's a few tokens left, so
+        // his bid hangs around until it expires.
+        let remaining = Currency::from(1_00) * SECONDS_PER_DAY
+                      - trace.segments[0].segment.spent;
+        assert_eq!(trace.segments[1].decisions, vec![
+            Decision::Outbid {
+                bid: "Alice", outbid: None, rate: Currency::from(0)
+            },
+            Decision::Skipped {
+                bid: "Partario",
+                needed: Currency::from(5_10) * Second::from(1),
+                expense_limit: remaining
+            },
+            Decision::Expired { bid: "Partario", at: EPOCH + SECONDS_PER_DAY },
+            Decision::Expired { bid: "Alice", at: EPOCH + 7 * SECONDS_PER_DAY }
+        ]);
+    }
+
+    #[test]
+    fn run_auction_traced_none_can_bid() {
+        let bids = vec![
+            Bid {
+                bid:           Currency::from(  5_00),
+                expense_limit: Token::from(     4_00),
+                expiry: Timestamp::from(10_000),
+                data: "Can't pay."
+            },
+            Bid {
+                bid:           Currency::from(  5_00),
+                expense_limit: Token::from(     6_00),
+                expiry: Timestamp::from(10_000),
+                data: "Can only pay once."
+            },
+            Bid {
+                bid:           Currency::from(  1_00),
+                expense_limit: Token::from(60_00_00),
+                expiry: Timestamp::from(10_000),
+                data: "Too cheap."
+            },
+            Bid {
+                bid:           Currency::from(  5_00),
+                expense_limit: Token::from(60_00_00),
+                expiry: Timestamp::from(0),
+                data: "Too late."
+            }
+        ];
+        let trace = run_auction_traced(
+            bids,
+            Currency::from(                       10),
+            Currency::from(                     4_60),
+            EPOCH
+        ).unwrap();
+        assert_eq!(trace, AuctionTrace {
+            dropped: vec![
+                Decision::Rejected {
+                    bid: "Can't pay.",
+                    reason: BidRejection::CantCoverOneSecond {
+                        needed: Token::from(4_60),
+                        expense_limit: Token::from(4_00)
+                    }
+                },
+                Decision::Rejected {
+                    bid: "Too cheap.",
+                    reason: BidRejection::BelowMinimum {
+                        min_bid: Currency::from(4_60)
+                    }
+                },
+                Decision::Rejected {
+                    bid: "Too late.",
+                    reason: BidRejection::ExpiryInPast {
+                        expiry: Timestamp::from(0), now: Timestamp::from(0)
+                    }
+                }
+            ],
+            segments: vec![TracedSegment {
+                segment: Segment {
+                    start: Timestamp::from(0),
+                    end: Timestamp::from(1),
+                    winner: "Can only pay once.",
+                    rate: Currency::from(4_60),
+                    spent: Token::from(460),
+                    runner_up: None
+                },
+                decisions: vec![
+                    Decision::Outbid {
+                        bid: "Can only pay once.", outbid: None,
+                        rate: Currency::from(4_60)
+                    },
+                    Decision::Exhausted {
+                        bid: "Can only pay once.",
+                        at: Timestamp::from(1),
+                        remaining: Token::from(1_40)
+                    }
+                ]
+            }]
+        });
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn auction_trace_serialises() {
+        let trace = run_auction_traced(
+            revenge_2_bids(),
+            Currency::from(                       10),
+            Currency::from(                        0),
+            EPOCH + SECONDS_PER_DAY
+        ).unwrap();
+        let json = serde_json::to_string(&trace).unwrap();
+        assert!(json.contains(r#"{"Outbid":{"bid":"Partario","outbid":"#));
+        assert_eq!(
+            serde_json::from_str::<AuctionTrace<&str>>(&json).unwrap(),
+            trace
         );
     }
 }
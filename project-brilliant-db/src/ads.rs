@@ -0,0 +1,184 @@
+use crate::{
+    DB, Error, AdBoxId, AdvertiserId, CreativeId, Advert,
+    query, to_sql, from_sql
+};
+use std::{error, fmt};
+
+/// Something to show in an ad box: an image, linking somewhere.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ad {
+    pub width: u64,
+    pub height: u64,
+    pub image_url: String,
+    pub link_url: String,
+    pub alt_text: String
+}
+
+/// Why [`DB::check_advert`] says an advert can't be bid on an ad box.
+///
+///   [`DB::check_advert`]: struct.DB.html#method.check_advert
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AdvertRejection {
+    UnknownCreative(CreativeId),
+    /// The creative belongs to a different advertiser.
+    NotTheAdvertisers { owner: AdvertiserId },
+    /// The creative isn't the ad box's size. Sizes are `(width, height)`.
+    WrongSize { creative: (u64, u64), ad_box: (u64, u64) }
+}
+
+impl fmt::Display for AdvertRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AdvertRejection::UnknownCreative(CreativeId(id)) => write!(
+                f, "no creative has id {}", id
+            ),
+            AdvertRejection::NotTheAdvertisers { .. } => write!(
+                f, "the creative belongs to another advertiser"
+            ),
+            AdvertRejection::WrongSize { creative, ad_box } => write!(
+                f, "the creative is {}x{}, but the ad box is {}x{}",
+                creative.0, creative.1, ad_box.0, ad_box.1
+            )
+        }
+    }
+}
+
+impl error::Error for AdvertRejection {}
+
+impl DB {
+    /// Whether `advert` can be bid on `ad_box`: its creative has to be its
+    /// advertiser's, and the ad box's size. The schema only enforces the
+    /// first, so check this before [`insert_bid`].
+    ///
+    ///   [`insert_bid`]: #method.insert_bid
+    pub fn check_advert(&self, ad_box: AdBoxId, advert: Advert)
+        -> Result<Result<(), AdvertRejection>, Error>
+    {
+        let rows = query(&self.0, "SELECT ad_boxes.width, ad_boxes.height, \
+                                   advertiser_id, creatives.width, \
+                                   creatives.height FROM ad_boxes \
+                                   LEFT JOIN creatives ON creatives.id = ? \
+                                   WHERE ad_boxes.id = ?",
+                         &[to_sql(advert.creative)?, to_sql(ad_box)?])?;
+        let row = rows.first().ok_or(Error::UnknownAdBox(ad_box))?;
+        let owner: AdvertiserId = match from_sql(&row[2])? {
+            Some(owner) => owner,
+            None => return Ok(Err(
+                AdvertRejection::UnknownCreative(advert.creative)
+            ))
+        };
+        if owner != advert.advertiser {
+            return Ok(Err(AdvertRejection::NotTheAdvertisers { owner }));
+        }
+        let ad_box_size = (from_sql(&row[0])?, from_sql(&row[1])?);
+        let creative_size = (from_sql(&row[3])?, from_sql(&row[4])?);
+        Ok(if creative_size == ad_box_size {
+            Ok(())
+        } else {
+            Err(AdvertRejection::WrongSize {
+                creative: creative_size, ad_box: ad_box_size
+            })
+        })
+    }
+
+    pub fn creative(&self, id: CreativeId) -> Result<Option<Ad>, Error> {
+        let rows = query(&self.0, "SELECT width, height, image_url, \
+                                   link_url, alt_text FROM creatives \
+                                   WHERE id = ?", &[to_sql(id)?])?;
+        rows.first().map(|row| Ok(Ad {
+            width: from_sql(&row[0])?,
+            height: from_sql(&row[1])?,
+            image_url: from_sql(&row[2])?,
+            link_url: from_sql(&row[3])?,
+            alt_text: from_sql(&row[4])?
+        })).transpose()
+    }
+
+    /// What an ad box shows when no bid is winning, if its publisher has
+    /// given it both an image and a link.
+    pub fn house_ad(&self, ad_box: AdBoxId) -> Result<Option<Ad>, Error> {
+        let rows = query(&self.0, "SELECT width, height, house_image_url, \
+                                   house_link_url FROM ad_boxes \
+                                   WHERE id = ?", &[to_sql(ad_box)?])?;
+        let row = rows.first().ok_or(Error::UnknownAdBox(ad_box))?;
+        let image_url: Option<String> = from_sql(&row[2])?;
+        let link_url: Option<String> = from_sql(&row[3])?;
+        Ok(match (image_url, link_url) {
+            (Some(image_url), Some(link_url)) => Some(Ad {
+                width: from_sql(&row[0])?,
+                height: from_sql(&row[1])?,
+                image_url,
+                link_url,
+                alt_text: String::new()
+            }),
+            _ => None
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ad_box_db;
+
+    #[test]
+    fn ads() {
+        let (db, ad_box, advert) = ad_box_db();
+        assert_eq!(db.creative(advert.creative).unwrap(), Some(Ad {
+            width: 120,
+            height: 600,
+            image_url: "https://example.com/ad.png".into(),
+            link_url: "https://example.com".into(),
+            alt_text: "An ad".into()
+        }));
+        assert_eq!(db.creative(CreativeId(2)).unwrap(), None);
+
+        assert_eq!(db.house_ad(ad_box).unwrap(), None);
+        query(&db.0, "UPDATE ad_boxes SET house_image_url = 'house.png', \
+                      house_link_url = 'https://example.org'", &[])
+            .unwrap();
+        assert_eq!(db.house_ad(ad_box).unwrap().unwrap().image_url,
+                   "house.png");
+        match db.house_ad(AdBoxId(2)) {
+            Err(Error::UnknownAdBox(AdBoxId(2))) => (),
+            x => panic!("expected an unknown ad box, got {:?}", x)
+        }
+    }
+
+    #[test]
+    fn check_advert() {
+        let (db, ad_box, advert) = ad_box_db();
+        assert_eq!(db.check_advert(ad_box, advert).unwrap(), Ok(()));
+
+        let other = db.insert_advertiser("Other").unwrap();
+        let theirs = Advert { advertiser: other, ..advert };
+        assert_eq!(db.check_advert(ad_box, theirs).unwrap(),
+                   Err(AdvertRejection::NotTheAdvertisers {
+                       owner: advert.advertiser
+                   }));
+
+        let unknown = Advert { creative: CreativeId(2), ..advert };
+        assert_eq!(db.check_advert(ad_box, unknown).unwrap(),
+                   Err(AdvertRejection::UnknownCreative(CreativeId(2))));
+
+        let banner = db.insert_creative(advert.advertiser, &Ad {
+            width: 468,
+            height: 60,
+            image_url: "https://example.com/banner.png".into(),
+            link_url: "https://example.com".into(),
+            alt_text: "A banner".into()
+        }).unwrap();
+        let banner = Advert { creative: banner, ..advert };
+        let rejection = db.check_advert(ad_box, banner).unwrap().unwrap_err();
+        assert_eq!(rejection, AdvertRejection::WrongSize {
+            creative: (468, 60), ad_box: (120, 600)
+        });
+        assert_eq!(rejection.to_string(),
+                   "the creative is 468x60, but the ad box is 120x600");
+
+        match db.check_advert(AdBoxId(2), advert) {
+            Err(Error::UnknownAdBox(AdBoxId(2))) => (),
+            x => panic!("expected an unknown ad box, got {:?}", x)
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use crate::{
+    DB, Error, AdBoxId, AdvertiserId, CreativeId,
+    query, to_sql, from_sql, transaction
+};
+use infinite_auction::{Bid, BidId, Amendment};
+use project_brilliant_utilities::{Timestamp, Token};
+use rustorm::Value;
+
+/// What a bid is for: whose ad it is, and what it shows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Advert {
+    pub advertiser: AdvertiserId,
+    pub creative: CreativeId
+}
+
+/// A bid, as it's stored.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StoredBid {
+    pub id: BidId,
+    pub ad_box: AdBoxId,
+    /// The bid as it was placed, or last amended. Its `expense_limit` is
+    /// the whole limit, counting what it's `spent` already.
+    pub bid: Bid<Advert>,
+    pub spent: Token,
+    pub placed_at: Timestamp
+}
+
+impl StoredBid {
+    /// What the bid has left to spend: its limit, less what it's spent.
+    /// This is the expense limit an `AuctionState` that's only seeing the
+    /// bid now should get.
+    pub fn remaining(&self) -> Token {
+        self.bid.expense_limit.saturating_sub(self.spent)
+    }
+}
+
+const COLUMNS: &str = "id, ad_box_id, advertiser_id, creative_id, bid, \
+                       expense_limit, spent, expiry, placed_at";
+
+fn stored_bid(row: &[Value]) -> Result<StoredBid, Error> {
+    Ok(StoredBid {
+        id: from_sql(&row[0])?,
+        ad_box: from_sql(&row[1])?,
+        bid: Bid {
+            bid: from_sql(&row[4])?,
+            expense_limit: from_sql(&row[5])?,
+            expiry: from_sql(&row[7])?,
+            data: Advert {
+                advertiser: from_sql(&row[2])?,
+                creative: from_sql(&row[3])?
+            }
+        },
+        spent: from_sql(&row[6])?,
+        placed_at: from_sql(&row[8])?
+    })
+}
+
+impl DB {
+    /// Store a new bid on an ad box. Nothing's been spent from it yet.
+    pub fn insert_bid(
+        &self, ad_box: AdBoxId, bid: &Bid<Advert>, placed_at: Timestamp
+    ) -> Result<BidId, Error> {
+        query(&self.0, "INSERT INTO bids (ad_box_id, advertiser_id, \
+                        creative_id, bid, expense_limit, spent, expiry, \
+                        placed_at) VALUES (?, ?, ?, ?, ?, 0, ?, ?)", &[
+            to_sql(ad_box)?,
+            to_sql(bid.data.advertiser)?,
+            to_sql(bid.data.creative)?,
+            to_sql(bid.bid)?,
+            to_sql(bid.expense_limit)?,
+            to_sql(bid.expiry)?,
+            to_sql(placed_at)?
+        ])?;
+        let rows = query(&self.0, "SELECT last_insert_rowid()", &[])?;
+        from_sql(&rows[0][0])
+    }
+
+    /// Change a bid that hasn't been cancelled, as of `at`. As with
+    /// [`AuctionState::amend_bid`], a new expense limit counts what the bid
+    /// has spent already. Returns whether there was such a bid.
+    ///
+    /// Amendments aren't dated, so the bid's ad box is settled up to `at`
+    /// first, in the same transaction, to charge what's been shown so far
+    /// on the old terms. If it's been settled past `at` already, the
+    /// amendment takes effect from there.
+    ///
+    ///   [`AuctionState::amend_bid`]: ../infinite_auction/struct.AuctionState.html#method.amend_bid
+    pub fn amend_bid(
+        &self, id: BidId, amendment: Amendment, at: Timestamp
+    ) -> Result<bool, Error> {
+        transaction(&self.0, || {
+            match self.get_bid(id)? {
+                Some(stored) => self.settle_ad_box(stored.ad_box, at)?,
+                None => return Ok(false)
+            };
+            self.amend(id, amendment)
+        })
+    }
+
+    fn amend(&self, id: BidId, amendment: Amendment) -> Result<bool, Error> {
+        let (sql, value) = match amendment {
+            Amendment::Bid(bid) => (
+                "UPDATE bids SET bid = ? \
+                 WHERE id = ? AND cancelled_at IS NULL",
+                to_sql(bid)?
+            ),
+            Amendment::ExpenseLimit(limit) => (
+                "UPDATE bids SET expense_limit = ? \
+                 WHERE id = ? AND cancelled_at IS NULL",
+                to_sql(limit)?
+            )
+        };
+        query(&self.0, sql, &[value, to_sql(id)?])?;
+        self.changed()
+    }
+
+    /// Cancel a bid, as of `at`. Returns whether there was a bid to cancel.
+    pub fn cancel_bid(&self, id: BidId, at: Timestamp) -> Result<bool, Error> {
+        query(&self.0, "UPDATE bids SET cancelled_at = ? \
+                        WHERE id = ? AND cancelled_at IS NULL",
+              &[to_sql(at)?, to_sql(id)?])?;
+        self.changed()
+    }
+
+    /// Look up a bid that hasn't been cancelled.
+    pub fn get_bid(&self, id: BidId) -> Result<Option<StoredBid>, Error> {
+        let rows = query(&self.0, &format!(
+            "SELECT {} FROM bids WHERE id = ? AND cancelled_at IS NULL",
+            COLUMNS
+        ), &[to_sql(id)?])?;
+        rows.first().map(|row| stored_bid(row)).transpose()
+    }
+
+    /// Every bid on an ad box that hasn't been cancelled, oldest first.
+    pub fn bids_for_ad_box(
+        &self, ad_box: AdBoxId
+    ) -> Result<Vec<StoredBid>, Error> {
+        query(&self.0, &format!(
+            "SELECT {} FROM bids WHERE ad_box_id = ? AND cancelled_at IS NULL \
+             ORDER BY id",
+            COLUMNS
+        ), &[to_sql(ad_box)?])?.iter().map(|row| stored_bid(row)).collect()
+    }
+
+    /// Whether the last statement changed anything.
+    fn changed(&self) -> Result<bool, Error> {
+        let rows = query(&self.0, "SELECT changes()", &[])?;
+        Ok(from_sql::<u64>(&rows[0][0])? != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ad_box_db, at, bid};
+    use infinite_auction::{AuctionState, Segment};
+    use project_brilliant_utilities::{Currency, ConvertValueError};
+
+    #[test]
+    fn bid_round_trip() {
+        let (db, ad_box, advert) = ad_box_db();
+        let bid = bid(advert, 5, 1000, 500);
+        let id = db.insert_bid(ad_box, &bid, at(0)).unwrap();
+        let stored = db.get_bid(id).unwrap().unwrap();
+        assert_eq!(stored, StoredBid {
+            id,
+            ad_box,
+            bid,
+            spent: Token::from(0),
+            placed_at: at(0)
+        });
+
+        assert!(db.amend_bid(id, Amendment::Bid(Currency::from(7)), at(0))
+                  .unwrap());
+        assert!(db.amend_bid(id, Amendment::ExpenseLimit(Token::from(700)),
+                             at(0)).unwrap());
+        let amended = db.get_bid(id).unwrap().unwrap().bid;
+        assert_eq!(amended.bid, Currency::from(7));
+        assert_eq!(amended.expense_limit, Token::from(700));
+
+        let other = db.insert_bid(ad_box, &bid, at(0)).unwrap();
+        assert_eq!(db.bids_for_ad_box(ad_box).unwrap().len(), 2);
+        assert!(db.cancel_bid(id, at(10)).unwrap());
+        assert!(!db.cancel_bid(id, at(20)).unwrap());
+        assert!(!db.amend_bid(id, Amendment::Bid(Currency::from(9)), at(20))
+                   .unwrap());
+        assert_eq!(db.get_bid(id).unwrap(), None);
+        let live: Vec<_> = db.bids_for_ad_box(ad_box).unwrap()
+                             .iter().map(|bid| bid.id).collect();
+        assert_eq!(live, [other]);
+        assert_eq!(db.bids_for_ad_box(AdBoxId(2)).unwrap(), []);
+    }
+
+    #[test]
+    fn bid_out_of_range() {
+        let (db, ad_box, advert) = ad_box_db();
+        let bid = Bid {
+            expense_limit: Token::max_value(),
+            ..bid(advert, 5, 0, 500)
+        };
+        match db.insert_bid(ad_box, &bid, at(0)) {
+            Err(Error::Conversion(ConvertValueError::OutOfRange)) => (),
+            x => panic!("expected an out-of-range error, got {:?}", x)
+        }
+        assert_eq!(db.bids_for_ad_box(ad_box).unwrap(), []);
+    }
+
+    #[test]
+    fn bid_unknown_creative() {
+        let (db, ad_box, advert) = ad_box_db();
+        let advert = Advert { creative: CreativeId(2), ..advert };
+        let bid = bid(advert, 5, 1000, 500);
+        match db.insert_bid(ad_box, &bid, at(0)) {
+            Err(Error::ConstraintViolation(_)) => (),
+            x => panic!("expected a constraint violation, got {:?}", x)
+        }
+    }
+
+    #[test]
+    fn bid_someone_elses_creative() {
+        let (db, ad_box, advert) = ad_box_db();
+        let other = db.insert_advertiser("Other").unwrap();
+        let advert = Advert { advertiser: other, ..advert };
+        let bid = bid(advert, 5, 1000, 500);
+        match db.insert_bid(ad_box, &bid, at(0)) {
+            Err(Error::ConstraintViolation(_)) => (),
+            x => panic!("expected a constraint violation, got {:?}", x)
+        }
+    }
+
+    #[test]
+    fn drive_auction() {
+        let (db, ad_box, advert) = ad_box_db();
+        let bid = bid(advert, 5, 1000, 500);
+        let id = db.insert_bid(ad_box, &bid, at(0)).unwrap();
+
+        let mut state = AuctionState::new(Currency::from(1),
+                                          Currency::from(1), at(0));
+        for stored in db.bids_for_ad_box(ad_box).unwrap() {
+            state.place_bid(stored.id, Bid {
+                expense_limit: stored.remaining(),
+                ..stored.bid
+            });
+        }
+        assert_eq!(state.advance_to(at(100)).unwrap(), [
+            Segment {
+                start: at(0),
+                end: at(100),
+                winner: advert,
+                rate: Currency::from(1),
+                spent: Token::from(100),
+                runner_up: None
+            }
+        ]);
+        assert_eq!(state.spent(id), Some(Token::from(100)));
+    }
+}
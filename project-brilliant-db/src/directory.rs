@@ -0,0 +1,186 @@
+use crate::{
+    DB, Error, Ad, AdBoxId, AdvertiserId, CreativeId, PublisherId,
+    Sql, query, to_sql, from_sql
+};
+use project_brilliant_utilities::Currency;
+use rustorm::Value;
+
+/// An ad box, as its publisher set it up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AdBox {
+    pub publisher: PublisherId,
+    pub name: String,
+    pub width: u64,
+    pub height: u64,
+    pub min_bid: Currency,
+    pub increment: Currency,
+    /// The platform's cut of what the ad box earns.
+    pub fee_percent: u64,
+    pub house_image_url: Option<String>,
+    pub house_link_url: Option<String>
+}
+
+impl DB {
+    pub fn insert_advertiser(
+        &self, name: &str
+    ) -> Result<AdvertiserId, Error> {
+        query(&self.0, "INSERT INTO advertisers (name) VALUES (?)",
+              &[to_sql(name.to_owned())?])?;
+        self.last_id()
+    }
+
+    pub fn insert_publisher(&self, name: &str) -> Result<PublisherId, Error> {
+        query(&self.0, "INSERT INTO publishers (name) VALUES (?)",
+              &[to_sql(name.to_owned())?])?;
+        self.last_id()
+    }
+
+    pub fn insert_ad_box(&self, ad_box: &AdBox) -> Result<AdBoxId, Error> {
+        query(&self.0, "INSERT INTO ad_boxes (publisher_id, name, width, \
+                        height, min_bid, increment, fee_percent, \
+                        house_image_url, house_link_url) \
+                        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)", &[
+            to_sql(ad_box.publisher)?,
+            to_sql(ad_box.name.clone())?,
+            to_sql(ad_box.width)?,
+            to_sql(ad_box.height)?,
+            to_sql(ad_box.min_bid)?,
+            to_sql(ad_box.increment)?,
+            to_sql(ad_box.fee_percent)?,
+            to_sql(ad_box.house_image_url.clone())?,
+            to_sql(ad_box.house_link_url.clone())?
+        ])?;
+        self.last_id()
+    }
+
+    pub fn insert_creative(
+        &self, advertiser: AdvertiserId, ad: &Ad
+    ) -> Result<CreativeId, Error> {
+        query(&self.0, "INSERT INTO creatives (advertiser_id, width, \
+                        height, image_url, link_url, alt_text) \
+                        VALUES (?, ?, ?, ?, ?, ?)", &[
+            to_sql(advertiser)?,
+            to_sql(ad.width)?,
+            to_sql(ad.height)?,
+            to_sql(ad.image_url.clone())?,
+            to_sql(ad.link_url.clone())?,
+            to_sql(ad.alt_text.clone())?
+        ])?;
+        self.last_id()
+    }
+
+    /// Every advertiser, and their name, oldest first.
+    pub fn advertisers(&self) -> Result<Vec<(AdvertiserId, String)>, Error> {
+        names(&query(&self.0, "SELECT id, name FROM advertisers \
+                               ORDER BY id", &[])?)
+    }
+
+    /// Every publisher, and their name, oldest first.
+    pub fn publishers(&self) -> Result<Vec<(PublisherId, String)>, Error> {
+        names(&query(&self.0, "SELECT id, name FROM publishers \
+                               ORDER BY id", &[])?)
+    }
+
+    /// Every ad box, oldest first.
+    pub fn ad_boxes(&self) -> Result<Vec<(AdBoxId, AdBox)>, Error> {
+        query(&self.0, "SELECT id, publisher_id, name, width, height, \
+                        min_bid, increment, fee_percent, house_image_url, \
+                        house_link_url FROM ad_boxes ORDER BY id", &[])?
+            .iter().map(|row| Ok((from_sql(&row[0])?, AdBox {
+                publisher: from_sql(&row[1])?,
+                name: from_sql(&row[2])?,
+                width: from_sql(&row[3])?,
+                height: from_sql(&row[4])?,
+                min_bid: from_sql(&row[5])?,
+                increment: from_sql(&row[6])?,
+                fee_percent: from_sql(&row[7])?,
+                house_image_url: from_sql(&row[8])?,
+                house_link_url: from_sql(&row[9])?
+            }))).collect()
+    }
+
+    /// Every creative, and whose it is, oldest first.
+    pub fn creatives(
+        &self
+    ) -> Result<Vec<(CreativeId, AdvertiserId, Ad)>, Error> {
+        query(&self.0, "SELECT id, advertiser_id, width, height, image_url, \
+                        link_url, alt_text FROM creatives ORDER BY id", &[])?
+            .iter().map(|row| Ok((
+                from_sql(&row[0])?,
+                from_sql(&row[1])?,
+                Ad {
+                    width: from_sql(&row[2])?,
+                    height: from_sql(&row[3])?,
+                    image_url: from_sql(&row[4])?,
+                    link_url: from_sql(&row[5])?,
+                    alt_text: from_sql(&row[6])?
+                }
+            ))).collect()
+    }
+
+    /// The id of the row that was inserted last.
+    fn last_id<T: Sql>(&self) -> Result<T, Error> {
+        let rows = query(&self.0, "SELECT last_insert_rowid()", &[])?;
+        from_sql(&rows[0][0])
+    }
+}
+
+fn names<T: Sql>(rows: &[Vec<Value>]) -> Result<Vec<(T, String)>, Error> {
+    rows.iter().map(|row| Ok((from_sql(&row[0])?, from_sql(&row[1])?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::open_memory_db;
+
+    #[test]
+    fn directory() {
+        let db = open_memory_db().unwrap();
+        let advertiser = db.insert_advertiser("Advertiser").unwrap();
+        let publisher = db.insert_publisher("Publisher").unwrap();
+        assert_eq!(db.advertisers().unwrap(),
+                   [(advertiser, "Advertiser".to_string())]);
+        assert_eq!(db.publishers().unwrap(),
+                   [(publisher, "Publisher".to_string())]);
+
+        let ad_box = AdBox {
+            publisher,
+            name: "Sidebar".into(),
+            width: 120,
+            height: 600,
+            min_bid: Currency::from(100),
+            increment: Currency::from(10),
+            fee_percent: 15,
+            house_image_url: None,
+            house_link_url: None
+        };
+        let id = db.insert_ad_box(&ad_box).unwrap();
+        assert_eq!(db.ad_boxes().unwrap(), [(id, ad_box.clone())]);
+        match db.insert_ad_box(&AdBox {
+            fee_percent: 101,
+            ..ad_box.clone()
+        }) {
+            Err(Error::ConstraintViolation(_)) => (),
+            x => panic!("expected a constraint violation, got {:?}", x)
+        }
+        match db.insert_ad_box(&AdBox {
+            publisher: PublisherId(2),
+            ..ad_box
+        }) {
+            Err(Error::ConstraintViolation(_)) => (),
+            x => panic!("expected a constraint violation, got {:?}", x)
+        }
+
+        let ad = Ad {
+            width: 120,
+            height: 600,
+            image_url: "https://example.com/ad.png".into(),
+            link_url: "https://example.com".into(),
+            alt_text: "An ad".into()
+        };
+        let creative = db.insert_creative(advertiser, &ad).unwrap();
+        assert_eq!(db.creatives().unwrap(), [(creative, advertiser, ad)]);
+    }
+}
@@ -0,0 +1,313 @@
+use crate::{
+    DB, Error, Schema, MIGRATIONS, EMPTY_SCHEMA,
+    connect, execute, query, transaction, apply_migration, migrate_schema
+};
+use rustorm::{EntityManager, Value};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+
+/// The version of the [`Dump`] format this version writes, and the only one
+/// it reads.
+///
+///   [`Dump`]: struct.Dump.html
+pub const DUMP_FORMAT: u32 = 1;
+
+/// Everything in a database, ready to be written out as JSON.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Dump {
+    /// See [`DUMP_FORMAT`].
+    ///
+    ///   [`DUMP_FORMAT`]: constant.DUMP_FORMAT.html
+    pub format: u32,
+    /// The fingerprint of the database's schema, which says what the
+    /// tables mean.
+    pub schema: u64,
+    pub tables: BTreeMap<String, TableDump>
+}
+
+/// Every row of a table, in the order they were inserted. Each cell is an
+/// integer, a string or `null`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TableDump {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<serde_json::Value>>
+}
+
+impl DB {
+    /// Dump everything in the database.
+    pub fn export(&self) -> Result<Dump, Error> {
+        transaction(&self.0, || dump(&self.0))
+    }
+}
+
+fn dump(em: &EntityManager) -> Result<Dump, Error> {
+    let schema = Schema::from(em.get_all_tables()?);
+    let mut tables = BTreeMap::new();
+    for table in &schema.tables {
+        let columns: Vec<_> = table.columns.iter()
+                                   .map(|column| column.name.clone())
+                                   .collect();
+        let rows = query(em, &format!(
+            "SELECT {} FROM \"{}\" ORDER BY rowid",
+            columns.iter().map(|column| format!("\"{}\"", column))
+                   .collect::<Vec<_>>().join(", "),
+            table.name
+        ), &[])?;
+        let rows = rows.into_iter().map(|row| {
+            row.into_iter().zip(&columns).map(|(value, column)| match value {
+                Value::Bigint(x) => Ok(x.into()),
+                Value::Text(s) => Ok(s.into()),
+                Value::Nil => Ok(serde_json::Value::Null),
+                _ => Err(Error::InvalidDump(format!(
+                    "table {} has an unsupported value in column {}",
+                    table.name, column
+                )))
+            }).collect()
+        }).collect::<Result<_, _>>()?;
+        tables.insert(table.name.clone(), TableDump { columns, rows });
+    }
+    Ok(Dump {
+        format: DUMP_FORMAT,
+        schema: schema.fingerprint(),
+        tables
+    })
+}
+
+/// Load a dump into the database at `uri`, which must be empty, then bring
+/// it up to date as [`open_db`] would. A dump from an older version is
+/// loaded into the schema it was taken from, and migrated from there.
+///
+///   [`open_db`]: fn.open_db.html
+pub fn import_db(uri: &str, dump: &Dump) -> Result<DB, Error> {
+    if dump.format != DUMP_FORMAT {
+        return Err(Error::UnsupportedDump { format: dump.format });
+    }
+    let migrations = if dump.schema == EMPTY_SCHEMA {
+        0
+    } else {
+        MIGRATIONS.iter().position(|m| m.to == dump.schema)
+                  .ok_or(Error::UnknownSchema { fingerprint: dump.schema })?
+            + 1
+    };
+
+    let em = connect(uri)?;
+    execute(&em, "PRAGMA foreign_keys = ON")?;
+    if Schema::from(em.get_all_tables()?).fingerprint() != EMPTY_SCHEMA {
+        return Err(Error::NotEmpty);
+    }
+    transaction(&em, || {
+        for migration in &MIGRATIONS[..migrations] {
+            apply_migration(&em, migration)?;
+        }
+        // The rows go in a table at a time, so they can refer to rows that
+        // aren't in yet.
+        execute(&em, "PRAGMA defer_foreign_keys = ON")?;
+        load(&em, dump)
+    })?;
+    migrate_schema(&em)?;
+    Ok(DB(em))
+}
+
+/// Check a dump has exactly the tables and columns of the schema it says
+/// it's from, before any of it is loaded, so a mislabelled dump is turned
+/// away rather than half loaded, or loaded with columns left out.
+fn check_tables(schema: &Schema, dump: &Dump) -> Result<(), Error> {
+    if schema.fingerprint() != dump.schema {
+        return Err(Error::UnknownSchema { fingerprint: dump.schema });
+    }
+    let invalid = |name: &str, problem: String| {
+        Err(Error::InvalidDump(format!("table {} {}", name, problem)))
+    };
+    for (name, table) in &dump.tables {
+        // Names go into the SQL as they are, so they'd better be real.
+        let found = match schema.table(name) {
+            Some(found) => found,
+            None => return invalid(name, "isn't in the schema".into())
+        };
+        for column in &table.columns {
+            if found.column(column).is_none() {
+                return invalid(name, format!("has no column {}", column));
+            }
+        }
+        for column in &found.columns {
+            if !table.columns.contains(&column.name) {
+                return invalid(name, format!("is missing column {}",
+                                             column.name));
+            }
+        }
+    }
+    for table in &schema.tables {
+        if !dump.tables.contains_key(&table.name) {
+            return invalid(&table.name, "is missing".into());
+        }
+    }
+    Ok(())
+}
+
+fn load(em: &EntityManager, dump: &Dump) -> Result<(), Error> {
+    check_tables(&Schema::from(em.get_all_tables()?), dump)?;
+    for (name, table) in &dump.tables {
+        let invalid = |problem: String| {
+            Err(Error::InvalidDump(format!("table {} {}", name, problem)))
+        };
+        let sql = format!(
+            "INSERT INTO \"{}\" ({}) VALUES ({})", name,
+            table.columns.iter().map(|column| format!("\"{}\"", column))
+                 .collect::<Vec<_>>().join(", "),
+            vec!["?"; table.columns.len()].join(", ")
+        );
+        for row in &table.rows {
+            if row.len() != table.columns.len() {
+                return invalid(format!(
+                    "has a row with {} values, not {}",
+                    row.len(), table.columns.len()
+                ));
+            }
+            let mut params = Vec::with_capacity(row.len());
+            for (column, cell) in table.columns.iter().zip(row) {
+                params.push(match cell {
+                    serde_json::Value::Null => Value::Nil,
+                    serde_json::Value::String(s) => Value::Text(s.clone()),
+                    cell => match cell.as_i64() {
+                        Some(x) => Value::Bigint(x),
+                        None => return invalid(format!(
+                            "has {} in column {}, which isn't an integer, \
+                             a string or null", cell, column
+                        ))
+                    }
+                });
+            }
+            query(em, &sql, &params)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        open_db, open_memory_db, current_schema, IN_MEMORY,
+        testing::{ad_box_db, at, bid}, tests::temp_sqlite_uri
+    };
+    use infinite_auction::Bid;
+    use project_brilliant_utilities::Currency;
+
+    #[test]
+    fn export_and_import() {
+        let (db, ad_box, advert) = ad_box_db();
+        query(&db.0, "UPDATE ad_boxes SET fee_percent = 15", &[]).unwrap();
+        let bid = bid(advert, 5, 1000, 500);
+        let low = db.insert_bid(ad_box, &bid, at(0)).unwrap();
+        let high = db.insert_bid(ad_box, &Bid {
+            bid: Currency::from(10),
+            ..bid
+        }, at(50)).unwrap();
+        db.cancel_bid(high, at(80)).unwrap();
+        db.settle_ad_box(ad_box, at(100)).unwrap();
+
+        let dump = db.export().unwrap();
+        assert_eq!(dump.tables["bids"].rows.len(), 2);
+        assert_eq!(dump.tables["spend_ledger"].rows.len(), 3);
+        let json = serde_json::to_string(&dump).unwrap();
+        let imported = import_db(IN_MEMORY, &serde_json::from_str(&json)
+                                                 .unwrap()).unwrap();
+        assert_eq!(imported.export().unwrap(), dump);
+        assert_eq!(imported.get_bid(low).unwrap(), db.get_bid(low).unwrap());
+        assert_eq!(imported.shown_at(ad_box, at(60))
+                           .unwrap().unwrap().bid, high);
+        imported.check_ledger().unwrap();
+
+        // The new rows go on after the old ones.
+        assert_eq!(imported.insert_bid(ad_box, &bid, at(0)).unwrap().0, 3);
+    }
+
+    #[test]
+    fn export_unsupported_value() {
+        let (db, _, _) = ad_box_db();
+        query(&db.0, "UPDATE publishers SET name = x'00'", &[]).unwrap();
+        match db.export() {
+            Err(Error::InvalidDump(problem)) => assert_eq!(
+                problem, "table publishers has an unsupported value in \
+                          column name"
+            ),
+            x => panic!("expected an invalid dump, got {:?}", x)
+        }
+    }
+
+    #[test]
+    fn import_old_dump() {
+        let em = connect(IN_MEMORY).unwrap();
+        transaction(&em, || apply_migration(&em, &MIGRATIONS[0])).unwrap();
+        query(&em, "INSERT INTO publishers (id, name) VALUES (1, 'P')", &[])
+            .unwrap();
+        query(&em, "INSERT INTO ad_boxes (id, publisher_id, name, width, \
+                    height, min_bid, increment) \
+                    VALUES (1, 1, 'Box', 1, 1, 1, 1)", &[]).unwrap();
+        let dump = dump(&em).unwrap();
+        assert_eq!(dump.schema, MIGRATIONS[0].to);
+
+        let db = import_db(IN_MEMORY, &dump).unwrap();
+        assert_eq!(db.export().unwrap().schema, current_schema());
+        let rows = query(&db.0, "SELECT settled_until, fee_percent \
+                                 FROM ad_boxes", &[]).unwrap();
+        assert_eq!(rows, [[Value::Bigint(0), Value::Bigint(0)]]);
+    }
+
+    #[test]
+    fn import_bad_dump() {
+        let dump = open_memory_db().unwrap().export().unwrap();
+        match import_db(IN_MEMORY, &Dump { format: 2, ..dump.clone() }) {
+            Err(Error::UnsupportedDump { format: 2 }) => (),
+            x => panic!("expected an unsupported dump, got {:?}", x.err())
+        }
+        match import_db(IN_MEMORY, &Dump { schema: 1, ..dump.clone() }) {
+            Err(Error::UnknownSchema { fingerprint: 1 }) => (),
+            x => panic!("expected an unknown schema, got {:?}", x.err())
+        }
+        // The tables don't match the schema the dump claims to be from.
+        let schema = MIGRATIONS[0].to;
+        match import_db(IN_MEMORY, &Dump { schema, ..dump.clone() }) {
+            Err(Error::InvalidDump(problem)) => assert_eq!(
+                problem, "table ad_boxes has no column fee_percent"
+            ),
+            x => panic!("expected an invalid dump, got {:?}", x.err())
+        }
+        let mut bad = dump.clone();
+        bad.tables.remove("clicks");
+        match import_db(IN_MEMORY, &bad) {
+            Err(Error::InvalidDump(problem)) => {
+                assert_eq!(problem, "table clicks is missing");
+            },
+            x => panic!("expected an invalid dump, got {:?}", x.err())
+        }
+
+        let mut bad = dump.clone();
+        bad.tables.get_mut("publishers").unwrap().rows
+           .push(vec![1.into(), true.into()]);
+        match import_db(IN_MEMORY, &bad) {
+            Err(Error::InvalidDump(problem)) => assert_eq!(
+                problem, "table publishers has true in column name, which \
+                          isn't an integer, a string or null"
+            ),
+            x => panic!("expected an invalid dump, got {:?}", x.err())
+        }
+
+        let mut bad = dump.clone();
+        bad.tables.get_mut("creatives").unwrap().rows.push(vec![
+            1.into(), 1.into(), 1.into(), 1.into(),
+            "".into(), "".into(), "".into()
+        ]);
+        match import_db(IN_MEMORY, &bad) {
+            Err(Error::ConstraintViolation(_)) => (),
+            x => panic!("expected a constraint violation, got {:?}", x.err())
+        }
+
+        let uri = temp_sqlite_uri();
+        open_db(&uri).unwrap();
+        match import_db(&uri, &dump) {
+            Err(Error::NotEmpty) => (),
+            x => panic!("expected an empty database, got {:?}", x.err())
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{
+    DB, Error, AdvertiserId, PublisherId,
+    query, to_sql, from_sql
+};
+use project_brilliant_utilities::Token;
+use rustorm::EntityManager;
+
+/// Somewhere tokens are paid from, or to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Account {
+    Advertiser(AdvertiserId),
+    Publisher(PublisherId),
+    /// The platform's cut.
+    Platform
+}
+
+/// Everything an account has paid and been paid.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Balance {
+    pub debits: Token,
+    pub credits: Token
+}
+
+impl DB {
+    pub fn balance(&self, account: Account) -> Result<Balance, Error> {
+        use Account::*;
+        let (condition, params) = match account {
+            Advertiser(id) => ("advertiser_id = ?", vec![to_sql(id)?]),
+            Publisher(id) => ("publisher_id = ?", vec![to_sql(id)?]),
+            Platform => (
+                "advertiser_id IS NULL AND publisher_id IS NULL",
+                vec![]
+            )
+        };
+        let rows = query(&self.0, &format!(
+            "SELECT COALESCE(SUM(debit), 0), COALESCE(SUM(credit), 0) \
+             FROM ledger_entries WHERE {}",
+            condition
+        ), &params)?;
+        Ok(Balance {
+            debits: from_sql(&rows[0][0])?,
+            credits: from_sql(&rows[0][1])?
+        })
+    }
+
+    /// Check that the ledger adds up: that every spend ledger row's entries
+    /// debit and credit exactly what it spent, and that all the debits and
+    /// credits come to the same.
+    pub fn check_ledger(&self) -> Result<(), Error> {
+        let rows = query(&self.0, "SELECT spend_ledger.id, \
+                                   COALESCE(SUM(debit), 0) AS debits, \
+                                   COALESCE(SUM(credit), 0) AS credits \
+                                   FROM spend_ledger LEFT JOIN ledger_entries \
+                                   ON spend_id = spend_ledger.id \
+                                   GROUP BY spend_ledger.id \
+                                   HAVING debits != spend_ledger.spent \
+                                   OR credits != spend_ledger.spent \
+                                   ORDER BY spend_ledger.id LIMIT 1", &[])?;
+        if let Some(row) = rows.first() {
+            return Err(Error::Unbalanced {
+                spend: Some(from_sql(&row[0])?),
+                debits: from_sql(&row[1])?,
+                credits: from_sql(&row[2])?
+            });
+        }
+
+        let rows = query(&self.0, "SELECT COALESCE(SUM(debit), 0), \
+                                   COALESCE(SUM(credit), 0) \
+                                   FROM ledger_entries", &[])?;
+        let debits = from_sql(&rows[0][0])?;
+        let credits = from_sql(&rows[0][1])?;
+        if debits != credits {
+            return Err(Error::Unbalanced { spend: None, debits, credits });
+        }
+        Ok(())
+    }
+}
+
+/// Record who paid whom for `spent` tokens of spend ledger row `spend`:
+/// the advertiser pays it all, and the publisher gets it, less the
+/// platform's `fee_percent`.
+pub(crate) fn record_payment(
+    em: &EntityManager, spend: u64, advertiser: AdvertiserId,
+    publisher: PublisherId, fee_percent: u64, spent: Token
+) -> Result<(), Error> {
+    // Rounded down, without overflowing.
+    let tokens = u64::from(spent);
+    let fee = Token::from(
+        tokens / 100 * fee_percent + tokens % 100 * fee_percent / 100
+    );
+    let entries = [
+        (Some(advertiser), None, spent, Token::from(0)),
+        (None, Some(publisher), Token::from(0), spent - fee),
+        (None, None, Token::from(0), fee)
+    ];
+    for &(advertiser, publisher, debit, credit) in &entries {
+        if debit == Token::from(0) && credit == Token::from(0) {
+            continue;
+        }
+        query(em, "INSERT INTO ledger_entries (spend_id, advertiser_id, \
+                   publisher_id, debit, credit) VALUES (?, ?, ?, ?, ?)", &[
+            to_sql(spend)?, to_sql(advertiser)?, to_sql(publisher)?,
+            to_sql(debit)?, to_sql(credit)?
+        ])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ad_box_db, at, bid};
+
+    #[test]
+    fn ledger_balances() {
+        let (db, ad_box, advert) = ad_box_db();
+        query(&db.0, "UPDATE ad_boxes SET fee_percent = 15", &[]).unwrap();
+        db.insert_bid(ad_box, &bid(advert, 5, 1000, 500), at(0)).unwrap();
+        db.settle_ad_box(ad_box, at(99)).unwrap();
+        db.settle_ad_box(ad_box, at(200)).unwrap();
+
+        let balance = |account, debits, credits| {
+            assert_eq!(db.balance(account).unwrap(), Balance {
+                debits: Token::from(debits),
+                credits: Token::from(credits)
+            });
+        };
+        balance(Account::Advertiser(advert.advertiser), 200, 0);
+        // 14 tokens of the first 99 go to the platform, then 15 of the
+        // next 101.
+        balance(Account::Publisher(PublisherId(1)), 0, 171);
+        balance(Account::Platform, 0, 29);
+        balance(Account::Publisher(PublisherId(2)), 0, 0);
+        db.check_ledger().unwrap();
+
+        query(&db.0, "UPDATE ledger_entries SET credit = credit + 1 \
+                      WHERE id = 2", &[]).unwrap();
+        match db.check_ledger() {
+            Err(Error::Unbalanced { spend: Some(_), debits, credits }) => {
+                assert_eq!(debits, Token::from(200));
+                assert_eq!(credits, Token::from(201));
+            },
+            x => panic!("expected an unbalanced ledger, got {:?}", x)
+        }
+    }
+}
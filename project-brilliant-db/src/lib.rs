@@ -1,159 +1,557 @@
-use rustorm::{EntityManager, Pool, table::Table, error::DbError};
-use core::hash::{SipHasher, Hash, Hasher};
-use core::mem::drop;
-use core::hint::unreachable_unchecked;
-use std::path::Path;
+use rustorm::{
+    EntityManager, Pool, ToValue, Value, table::Table, error::DbError
+};
+use infinite_auction::{BidId, AuctionError};
+use project_brilliant_utilities::{
+    Second, Currency, Token, Timestamp, ConvertValueError, SqlInteger
+};
+use std::{convert::TryFrom, error, fmt};
+
+mod schema;
+mod bids;
+mod settle;
+mod ledger;
+mod timeline;
+mod dump;
+mod ads;
+mod tracking;
+mod directory;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub use schema::*;
+pub use bids::*;
+pub use ledger::*;
+pub use timeline::*;
+pub use dump::*;
+pub use ads::*;
+pub use tracking::*;
+pub use directory::*;
 
 pub struct DB(pub EntityManager);
+
+impl DB {
+    /// Run `f` in a transaction, so that if it fails, none of its changes
+    /// are kept. The methods that make more than one change already use
+    /// one; this is for doing several of them together.
+    pub fn transaction<T, E: From<Error>>(
+        &self, f: impl FnOnce() -> Result<T, E>
+    ) -> Result<T, E> {
+        transaction(&self.0, f)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AdBoxId(pub u64);
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AdvertiserId(pub u64);
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CreativeId(pub u64);
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PublisherId(pub u64);
+
 #[derive(Debug)]
-pub struct Error(Option<DbError>);
+pub enum Error {
+    /// The database couldn't be opened.
+    Connection(DbError),
+    /// The database's schema has a fingerprint no migration starts from, so
+    /// it's either from a newer version or has been edited by hand.
+    UnknownSchema { fingerprint: u64 },
+    /// The migration at index `step` of the migration list failed, and was
+    /// rolled back.
+    Migration { step: usize, cause: Box<Error> },
+    /// A statement would have broken one of the schema's constraints.
+    ConstraintViolation(DbError),
+    /// A migration left rows in `table` referring to rows that aren't
+    /// there, so it was rolled back.
+    DanglingReference { table: String },
+    /// Anything else that went wrong running a statement.
+    Query(DbError),
+    /// A number couldn't be stored, or what was stored couldn't be read as
+    /// one.
+    Conversion(ConvertValueError),
+    /// What was stored should have been text, but wasn't.
+    NotText,
+    UnknownAdBox(AdBoxId),
+    UnknownBid(BidId),
+    /// Running an auction went wrong.
+    Auction(AuctionError),
+    /// The ledger doesn't add up. The entries for `spend` (a spend ledger
+    /// row, or the whole ledger if `None`) should debit and credit the
+    /// same amount, which for a row is what it spent.
+    Unbalanced { spend: Option<u64>, debits: Token, credits: Token },
+    /// A dump is in a format this version doesn't know.
+    UnsupportedDump { format: u32 },
+    /// A dump doesn't fit its own schema, or the database holds a value a
+    /// dump can't.
+    InvalidDump(String),
+    /// A dump can only be imported into an empty database.
+    NotEmpty
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Error::*;
+        match self {
+            Connection(e) => write!(f, "couldn't open the database: {}", e),
+            UnknownSchema { fingerprint } => write!(
+                f, "the database schema (fingerprint {}) isn't one this \
+                    version knows how to upgrade; it may be from a newer \
+                    version, or have been edited by hand", fingerprint
+            ),
+            Migration { step, cause } => write!(
+                f, "migration step {} failed and was rolled back: {}",
+                step, cause
+            ),
+            ConstraintViolation(e) => write!(
+                f, "a database constraint was violated: {}", e
+            ),
+            DanglingReference { table } => write!(
+                f, "rows in table {} refer to rows that don't exist", table
+            ),
+            Query(e) => write!(f, "a database query failed: {}", e),
+            Conversion(e) => write!(f, "{}", e),
+            NotText => write!(f, "the database value isn't text"),
+            UnknownAdBox(AdBoxId(id)) => write!(f, "no ad box has id {}", id),
+            UnknownBid(BidId(id)) => write!(f, "no bid has id {}", id),
+            Auction(e) => write!(f, "the auction failed: {}", e),
+            Unbalanced { spend: Some(spend), debits, credits } => write!(
+                f, "the ledger entries for spend {} don't add up: they \
+                    debit {} tokens and credit {}", spend,
+                u64::from(*debits), u64::from(*credits)
+            ),
+            Unbalanced { spend: None, debits, credits } => write!(
+                f, "the ledger doesn't add up: it debits {} tokens and \
+                    credits {}", u64::from(*debits), u64::from(*credits)
+            ),
+            UnsupportedDump { format } => write!(
+                f, "the dump is in format {}, but this version only reads \
+                    format {}", format, DUMP_FORMAT
+            ),
+            InvalidDump(problem) => write!(f, "invalid dump: {}", problem),
+            NotEmpty => write!(f, "the database isn't empty")
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use Error::*;
+        match self {
+            Connection(e) | ConstraintViolation(e) | Query(e) => Some(e),
+            UnknownSchema { .. } | UnknownAdBox(_) | UnknownBid(_)
+            | DanglingReference { .. } | NotText | Unbalanced { .. }
+            | UnsupportedDump { .. } | InvalidDump(_) | NotEmpty => None,
+            Conversion(e) => Some(e),
+            Auction(e) => Some(e),
+            Migration { cause, .. } => Some(cause.as_ref())
+        }
+    }
+}
 
 impl From<DbError> for Error {
-    fn from(error: DbError) -> Error { Error(Some(error)) }
+    fn from(error: DbError) -> Error {
+        if is_constraint_violation(&error) {
+            Error::ConstraintViolation(error)
+        } else {
+            Error::Query(error)
+        }
+    }
+}
+
+/// Whether SQLite refused to run a statement because it would have broken
+/// a constraint, going by its result code.
+fn is_constraint_violation(error: &DbError) -> bool {
+    // rustorm keeps SQLite's error a couple of sources down.
+    let mut cause: Option<&(dyn error::Error + 'static)> = match error {
+        DbError::PlatformError(e) => Some(e),
+        _ => None
+    };
+    while let Some(e) = cause {
+        if let Some(rusqlite::Error::SqliteFailure(e, _)) = e.downcast_ref() {
+            return e.code == rusqlite::ErrorCode::ConstraintViolation;
+        }
+        cause = e.source();
+    }
+    false
+}
+
+/// The URI of a database that's never written to disk, and is gone once
+/// the [`DB`] is dropped. Every database opened with it is a new one.
+///
+///   [`DB`]: struct.DB.html
+pub const IN_MEMORY: &str = "sqlite::memory:";
+
+fn connect(uri: &str) -> Result<EntityManager, Error> {
+    if uri != IN_MEMORY {
+        return Pool::new().em(uri).map_err(Error::Connection);
+    }
+    // rustorm hands SQLite the URI's host and path, so there's no spelling
+    // `:memory:`, and it doesn't let us open the connection ourselves. An
+    // empty path gets a private database instead, which SQLite keeps in
+    // its page cache, only backing it with a temporary file once the
+    // cache is full. So the cache is made as good as unlimited, and
+    // everything else that could use a file is kept in memory too.
+    let em = Pool::new().em("sqlite://").map_err(Error::Connection)?;
+    for pragma in &[
+        "PRAGMA cache_size = -1000000000",
+        "PRAGMA cache_spill = OFF",
+        "PRAGMA journal_mode = MEMORY",
+        "PRAGMA temp_store = MEMORY"
+    ] {
+        execute(&em, pragma)?;
+    }
+    Ok(em)
 }
 
 pub fn open_db(uri: &str) -> Result<DB, Error> {
-    let em = Pool::new().em(uri)?;
-    migrate_schema(hash_schema(em.get_all_tables()?))?;
+    let em = connect(uri)?;
+    execute(&em, "PRAGMA foreign_keys = ON")?;
+    migrate_schema(&em)?;
     Ok(DB(em))
 }
 
-fn hash_schema(mut tables: Vec<Table>) -> u64 {
-    use rustorm::{
-        table::TableKey::*,
-        column::{Capacity::*, ColumnConstraint::*, Literal::*},
-    };
+/// Open a new, up-to-date database in memory. See [`IN_MEMORY`].
+///
+///   [`IN_MEMORY`]: constant.IN_MEMORY.html
+pub fn open_memory_db() -> Result<DB, Error> {
+    open_db(IN_MEMORY)
+}
 
-    tables.sort_unstable_by_key(Table::complete_name);
-    let mut hasher = SipHasher::new();
-    let h = &mut hasher;
-
-    for table in tables {
-        table.name.name.hash(h);
-        let mut columns = table.columns.clone();
-        columns.sort_unstable_by(|a, b| a.name.name.cmp(&b.name.name));
-        for column in columns {
-            column.name.name.hash(h);
-            let spec = column.specification;
-            spec.sql_type.name().hash(h);
-            match spec.capacity {
-                Some(x) => match x {
-                    Limit(x) => {
-                        "Limit".hash(h);
-                        x.hash(h);
-                    },
-                    Range(x, y) => {
-                        "Range".hash(h);
-                        x.hash(h);
-                        y.hash(h);
-                    }
-                },
-                None => { "None".hash(h); }
-            };
-            let mut constraints = spec.constraints.clone();
-            constraints.sort_unstable_by_key(|x| match x {
-                NotNull => 0,
-                DefaultValue(_) => 1,
-                AutoIncrement => 2
-            });
-            for constraint in constraints {
-                match constraint {
-                    NotNull => "NotNull".hash(h),
-                    DefaultValue(x) => {
-                        "DefaultValue".hash(h);
-                        match x {
-                            Bool(x) => { "Bool".hash(h); x.hash(h) },
-                            Null => "Null".hash(h),
-                            Integer(x) => { "Integer".hash(h); x.hash(h) },
-                            Double(x) => {
-                                "Double".hash(h);
-                                x.to_bits().hash(h)
-                            },
-                            UuidGenerateV4 => "UuidGenerateV4".hash(h),
-                            Uuid(x) => {
-                                "Uuid".hash(h);
-                                x.as_bytes().hash(h)
-                            },
-                            String(x) => {"String".hash(h); x.hash(h) },
-                            Blob(x) => {"Blob".hash(h); x.hash(h) },
-                            CurrentTime => "CurrentTime".hash(h),
-                            CurrentDate => "CurrentDate".hash(h),
-                            CurrentTimestamp => "CurrentTimestamp".hash(h),
-                            ArrayInt(x) => { "ArrayInt".hash(h); x.hash(h) },
-                            ArrayFloat(x) => {
-                                "ArrayFloat".hash(h);
-                                for double in x {
-                                    double.to_bits().hash(h);
-                                }
-                            },
-                            ArrayString(x) => {
-                                "ArrayString".hash(h);
-                                x.hash(h)
-                            }
-                        }
-                    },
-                    AutoIncrement => "AutoIncrement".hash(h)
-                }
+/// Describe a database's schema as it is, without migrating it.
+pub fn describe_db(uri: &str) -> Result<Schema, Error> {
+    let em = connect(uri)?;
+    Ok(Schema::from(em.get_all_tables()?))
+}
+
+/// The schema a database should have once [`open_db`] has migrated it.
+///
+///   [`open_db`]: fn.open_db.html
+pub fn expected_schema() -> Result<Schema, Error> {
+    let DB(em) = open_memory_db()?;
+    Ok(Schema::from(em.get_all_tables()?))
+}
+
+/// Something stored as a single SQL value.
+trait Sql: Sized {
+    fn to_sql(self) -> Result<Value, Error>;
+    fn from_sql(value: &Value) -> Result<Self, Error>;
+}
+
+macro_rules! sql_integers {
+    ($($T:ident),*) => {$(
+        impl Sql for $T {
+            fn to_sql(self) -> Result<Value, Error> {
+                let x = SqlInteger::new(self).map_err(Error::Conversion)?;
+                Ok(x.to_value())
+            }
+            fn from_sql(value: &Value) -> Result<$T, Error> {
+                SqlInteger::try_from(value).map(SqlInteger::get)
+                                           .map_err(Error::Conversion)
             }
         }
-        table.is_view.hash(h);
-        let mut keys = table.table_key.clone();
-        keys.sort_unstable_by(|a, b| match a {
-            PrimaryKey(key) => (0, &key.name),
-            UniqueKey(key) => (1, &key.name),
-            Key(key) => (2, &key.name),
-            ForeignKey(fk) => (3, &fk.name)
-        }.cmp(&match b {
-            PrimaryKey(key) => (0, &key.name),
-            UniqueKey(key) => (1, &key.name),
-            Key(key) => (2, &key.name),
-            ForeignKey(fk) => (3, &fk.name)
-        }));
-        for tk in keys {
-            if let ForeignKey(fk) = tk {
-                "ForeignKey".hash(h);
-                fk.name.hash(h);
-                let mut columns = fk.columns.clone();
-                columns.sort_unstable_by(|a, b| a.name.cmp(&b.name));
-                for x in columns { x.name.hash(h) };
-                fk.foreign_table.name.hash(h);
-                let mut columns = fk.referred_columns.clone();
-                columns.sort_unstable_by(|a, b| a.name.cmp(&b.name));
-                for x in columns { x.name.hash(h) };
-            } else {
-                let key = match tk {
-                    PrimaryKey(key) => { "PrimaryKey".hash(h); key },
-                    UniqueKey(key) => { "UniqueKey".hash(h); key },
-                    Key(key) => { "Key".hash(h); key },
-                    ForeignKey(_) => unsafe { unreachable_unchecked() }
-                };
-                key.name.hash(h);
-                let mut columns = key.columns.clone();
-                columns.sort_unstable_by(|a, b| a.name.cmp(&b.name));
-                for x in columns { x.name.hash(h); }
-            };
+    )*}
+}
+// u64 isn't money, but it's stored the same way.
+sql_integers!(Second, Currency, Token, Timestamp, u64);
+
+impl Sql for String {
+    fn to_sql(self) -> Result<Value, Error> {
+        Ok(Value::Text(self))
+    }
+    fn from_sql(value: &Value) -> Result<String, Error> {
+        match value {
+            Value::Text(s) => Ok(s.clone()),
+            _ => Err(Error::NotText)
         }
-    };
-    h.finish()
+    }
+}
+
+/// `NULL` is `None`.
+impl<T: Sql> Sql for Option<T> {
+    fn to_sql(self) -> Result<Value, Error> {
+        self.map_or(Ok(Value::Nil), T::to_sql)
+    }
+    fn from_sql(value: &Value) -> Result<Option<T>, Error> {
+        match value {
+            Value::Nil => Ok(None),
+            value => T::from_sql(value).map(Some)
+        }
+    }
 }
 
-fn migrate_schema(from: u64) -> Result<(), Error> {
-    if from == 2202906307356721367 {
-        // empty
-        // populate!
+macro_rules! sql_ids {
+    ($($T:ident),*) => {$(
+        impl Sql for $T {
+            fn to_sql(self) -> Result<Value, Error> {
+                self.0.to_sql()
+            }
+            fn from_sql(value: &Value) -> Result<$T, Error> {
+                u64::from_sql(value).map($T)
+            }
+        }
+    )*}
+}
+sql_ids!(BidId, AdBoxId, AdvertiserId, CreativeId, PublisherId);
+
+fn to_sql<T: Sql>(x: T) -> Result<Value, Error> {
+    x.to_sql()
+}
+
+fn from_sql<T: Sql>(value: &Value) -> Result<T, Error> {
+    T::from_sql(value)
+}
+
+/// Run one SQL statement, and return the rows it produces.
+fn query(em: &EntityManager, sql: &str, params: &[Value])
+    -> Result<Vec<Vec<Value>>, Error>
+{
+    let params: Vec<_> = params.iter().collect();
+    Ok(em.db().execute_sql_with_return(sql, &params)?.data)
+}
+
+/// Run `f` in a transaction, which is rolled back if it fails, or can't be
+/// committed (say, because of a deferred constraint). In another
+/// transaction, it's a savepoint, so only what `f` did is rolled back, and
+/// nothing's committed until the outermost one is.
+fn transaction<T, E: From<Error>>(
+    em: &EntityManager, f: impl FnOnce() -> Result<T, E>
+) -> Result<T, E> {
+    execute(em, "SAVEPOINT tx")?;
+    match f().and_then(|x| Ok(execute(em, "RELEASE tx").map(|()| x)?)) {
+        Ok(x) => Ok(x),
+        Err(e) => {
+            execute(em, "ROLLBACK TO tx")?;
+            execute(em, "RELEASE tx")?;
+            Err(e)
+        }
+    }
+}
+
+/// Run one SQL statement, ignoring anything it returns. (SQLite only runs
+/// the first statement it's given, so there mustn't be more than one.)
+fn execute(em: &EntityManager, sql: &str) -> Result<(), Error> {
+    em.db().execute_sql_with_return(sql, &[])?;
+    Ok(())
+}
+
+fn hash_schema(tables: Vec<Table>) -> u64 {
+    Schema::from(tables).fingerprint()
+}
+
+/// The fingerprint of a database with nothing in it.
+const EMPTY_SCHEMA: u64 = 2202906307356721367;
+
+/// One step in bringing a database's schema up to date. `from` and `to`
+/// are the [`hash_schema`] fingerprints it expects before, and leaves
+/// after.
+///
+///   [`hash_schema`]: fn.hash_schema.html
+struct Migration {
+    from: u64,
+    to: u64,
+    statements: &'static [&'static str]
+}
+
+/// Every migration, oldest first. Each one's `from` is the last one's `to`.
+///
+/// Money is stored in the units of project-brilliant-utilities: `bid`,
+/// `min_bid`, `increment` and `rate` are `Currency`, `expense_limit` and
+/// `spent` are `Token`, and times are `Timestamp`s.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: EMPTY_SCHEMA,
+        to: 6400568517031011706,
+        statements: &[
+            "CREATE TABLE advertisers (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL
+            )",
+            "CREATE TABLE publishers (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL
+            )",
+            "CREATE TABLE ad_boxes (
+                id INTEGER PRIMARY KEY,
+                publisher_id INTEGER NOT NULL REFERENCES publishers (id),
+                name TEXT NOT NULL,
+                width INTEGER NOT NULL,
+                height INTEGER NOT NULL,
+                min_bid INTEGER NOT NULL,
+                increment INTEGER NOT NULL,
+                house_image_url TEXT,
+                house_link_url TEXT
+            )",
+            "CREATE TABLE creatives (
+                id INTEGER PRIMARY KEY,
+                advertiser_id INTEGER NOT NULL REFERENCES advertisers (id),
+                width INTEGER NOT NULL,
+                height INTEGER NOT NULL,
+                image_url TEXT NOT NULL,
+                link_url TEXT NOT NULL,
+                alt_text TEXT NOT NULL
+            )",
+            "CREATE TABLE bids (
+                id INTEGER PRIMARY KEY,
+                ad_box_id INTEGER NOT NULL REFERENCES ad_boxes (id),
+                advertiser_id INTEGER NOT NULL REFERENCES advertisers (id),
+                creative_id INTEGER NOT NULL REFERENCES creatives (id),
+                bid INTEGER NOT NULL,
+                expense_limit INTEGER NOT NULL,
+                spent INTEGER NOT NULL,
+                expiry INTEGER NOT NULL,
+                placed_at INTEGER NOT NULL,
+                cancelled_at INTEGER
+            )",
+            "CREATE TABLE spend_ledger (
+                id INTEGER PRIMARY KEY,
+                bid_id INTEGER NOT NULL REFERENCES bids (id),
+                shown_from INTEGER NOT NULL,
+                shown_until INTEGER NOT NULL,
+                rate INTEGER NOT NULL,
+                spent INTEGER NOT NULL
+            )"
+        ]
+    },
+    Migration {
+        from: 6400568517031011706,
+        to: 3149122244639668986,
+        statements: &[
+            // How far the ad box's auction has been run, and charged for.
+            "ALTER TABLE ad_boxes
+             ADD COLUMN settled_until INTEGER NOT NULL DEFAULT 0"
+        ]
+    },
+    Migration {
+        from: 3149122244639668986,
+        to: 2637792651598811651,
+        statements: &[
+            // The platform's cut of what the ad box earns.
+            "ALTER TABLE ad_boxes
+             ADD COLUMN fee_percent INTEGER NOT NULL DEFAULT 0
+             CHECK (fee_percent BETWEEN 0 AND 100)",
+            // Who paid whom, for each spend ledger row. An account is an
+            // advertiser, a publisher, or the platform if neither.
+            "CREATE TABLE ledger_entries (
+                id INTEGER PRIMARY KEY,
+                spend_id INTEGER NOT NULL REFERENCES spend_ledger (id),
+                advertiser_id INTEGER REFERENCES advertisers (id),
+                publisher_id INTEGER REFERENCES publishers (id),
+                debit INTEGER NOT NULL,
+                credit INTEGER NOT NULL
+            )"
+        ]
+    },
+    Migration {
+        from: 2637792651598811651,
+        to: 13156790185018402803,
+        statements: &[
+            // Each time an ad box's ad was served: the winning bid's, or
+            // the house ad if `bid_id` is NULL. The segment it was part of
+            // is the bid's spend ledger row covering `shown_at`.
+            "CREATE TABLE impressions (
+                id INTEGER PRIMARY KEY,
+                ad_box_id INTEGER NOT NULL REFERENCES ad_boxes (id),
+                bid_id INTEGER REFERENCES bids (id),
+                shown_at INTEGER NOT NULL,
+                link_url TEXT NOT NULL,
+                token TEXT NOT NULL UNIQUE
+            )",
+            "CREATE TABLE clicks (
+                id INTEGER PRIMARY KEY,
+                impression_id INTEGER NOT NULL REFERENCES impressions (id),
+                clicked_at INTEGER NOT NULL
+            )"
+        ]
+    },
+    Migration {
+        from: 13156790185018402803,
+        to: 1239345570398248740,
+        statements: &[
+            // A bid's creative has to be its advertiser's. SQLite can't add
+            // a foreign key to a table, so the bids are copied into a new
+            // one that has it.
+            "CREATE UNIQUE INDEX creatives_by_advertiser
+             ON creatives (id, advertiser_id)",
+            "CREATE TABLE new_bids (
+                id INTEGER PRIMARY KEY,
+                ad_box_id INTEGER NOT NULL REFERENCES ad_boxes (id),
+                advertiser_id INTEGER NOT NULL REFERENCES advertisers (id),
+                creative_id INTEGER NOT NULL REFERENCES creatives (id),
+                bid INTEGER NOT NULL,
+                expense_limit INTEGER NOT NULL,
+                spent INTEGER NOT NULL,
+                expiry INTEGER NOT NULL,
+                placed_at INTEGER NOT NULL,
+                cancelled_at INTEGER,
+                FOREIGN KEY (creative_id, advertiser_id)
+                    REFERENCES creatives (id, advertiser_id)
+            )",
+            "INSERT INTO new_bids SELECT * FROM bids",
+            "DROP TABLE bids",
+            "ALTER TABLE new_bids RENAME TO bids"
+        ]
+    }
+];
+
+/// The fingerprint of a database that's up to date.
+fn current_schema() -> u64 {
+    MIGRATIONS.last().map_or(EMPTY_SCHEMA, |migration| migration.to)
+}
+
+/// Run a migration's statements, and check it left the schema it should
+/// have. The caller deals with the transaction.
+fn apply_migration(em: &EntityManager, migration: &Migration)
+    -> Result<u64, Error>
+{
+    for sql in migration.statements {
+        execute(em, sql)?;
+    }
+    // Foreign keys are off while migrating, so check what the migration
+    // left behind before committing it.
+    let dangling = query(em, "PRAGMA foreign_key_check", &[])?;
+    if let Some(row) = dangling.first() {
+        return Err(Error::DanglingReference { table: from_sql(&row[0])? });
+    }
+    let fingerprint = hash_schema(em.get_all_tables()?);
+    if fingerprint == migration.to {
+        Ok(fingerprint)
+    } else {
+        Err(Error::UnknownSchema { fingerprint })
+    }
+}
+
+/// Bring the database's schema up to date, one migration at a time. Each
+/// migration is a transaction of its own, so one that fails leaves the
+/// database as the last one left it.
+fn migrate_schema(em: &EntityManager) -> Result<(), Error> {
+    let mut schema = hash_schema(em.get_all_tables()?);
+    let start = MIGRATIONS.iter().position(|m| m.from == schema)
+                          .unwrap_or_else(|| MIGRATIONS.len());
+    // A table that others refer to can only be dropped (to rebuild it) with
+    // foreign keys off, and SQLite ignores that pragma inside a transaction.
+    execute(em, "PRAGMA foreign_keys = OFF")?;
+    let migrated: Result<(), Error> = MIGRATIONS.iter().enumerate().skip(start)
+        .try_for_each(|(step, migration)| {
+            schema = transaction(em, || apply_migration(em, migration))
+                .map_err(|e| Error::Migration { step, cause: Box::new(e) })?;
+            Ok(())
+        });
+    execute(em, "PRAGMA foreign_keys = ON")?;
+    migrated?;
+    if schema == current_schema() {
         Ok(())
     } else {
-        // Unknown schema
-        Err(Error(None))
+        Err(Error::UnknownSchema { fingerprint: schema })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use tempfile::NamedTempFile;
+    use rustorm::column::{ColumnConstraint, Literal};
     use crate::*;
 
-    fn temp_sqlite_uri() -> String {
+    pub(crate) fn temp_sqlite_uri() -> String {
         let mut s = String::from("sqlite://");
         let x = NamedTempFile::new().unwrap().into_temp_path();
         s.push_str(x.to_str().unwrap().as_ref());
@@ -162,6 +560,254 @@ mod tests {
 
     #[test]
     fn initialise_db() {
-        let db = open_db(&temp_sqlite_uri()).unwrap();
+        let uri = temp_sqlite_uri();
+        let DB(em) = open_db(&uri).unwrap();
+        let tables = em.get_all_tables().unwrap();
+        assert_eq!(hash_schema(tables.clone()), current_schema());
+        let mut names: Vec<_> = tables.iter()
+                                      .map(|t| t.name.name.as_str())
+                                      .collect();
+        names.sort_unstable();
+        assert_eq!(names, ["ad_boxes", "advertisers", "bids", "clicks",
+                           "creatives", "impressions", "ledger_entries",
+                           "publishers", "spend_ledger"]);
+        drop(em);
+
+        // Already up to date
+        open_db(&uri).unwrap();
+    }
+
+    #[test]
+    fn schema_drift() {
+        let uri = temp_sqlite_uri();
+        let DB(em) = open_db(&uri).unwrap();
+        execute(&em, "ALTER TABLE advertisers ADD COLUMN notes TEXT")
+            .unwrap();
+        execute(&em, "DROP TABLE spend_ledger").unwrap();
+        drop(em);
+
+        let expected = expected_schema().unwrap();
+        assert_eq!(expected.fingerprint(), current_schema());
+        assert_eq!(expected.diff(&expected), []);
+        let differences = expected.diff(&describe_db(&uri).unwrap());
+        assert_eq!(differences, [
+            SchemaDifference::UnexpectedColumn {
+                table: "advertisers".into(),
+                column: ColumnSchema {
+                    name: "notes".into(),
+                    sql_type: "Text".into(),
+                    capacity: None,
+                    constraints: vec![
+                        ColumnConstraint::DefaultValue(Literal::Null)
+                    ]
+                }
+            },
+            SchemaDifference::MissingTable("spend_ledger".into())
+        ]);
+        assert_eq!(differences[0].to_string(),
+                   "table advertisers has unexpected column \
+                    `notes Text DEFAULT Null`");
+    }
+
+    #[test]
+    fn upgrade_db() {
+        let uri = temp_sqlite_uri();
+        let em = Pool::new().em(&uri).unwrap();
+        transaction(&em, || apply_migration(&em, &MIGRATIONS[0])).unwrap();
+        query(&em, "INSERT INTO publishers (id, name) VALUES (1, 'P')", &[])
+            .unwrap();
+        query(&em, "INSERT INTO ad_boxes (id, publisher_id, name, width, \
+                    height, min_bid, increment) \
+                    VALUES (1, 1, 'Box', 1, 1, 1, 1)", &[]).unwrap();
+        insert_old_bid(&em, 1);
+        drop(em);
+
+        let DB(em) = open_db(&uri).unwrap();
+        assert_eq!(hash_schema(em.get_all_tables().unwrap()),
+                   current_schema());
+        let rows = query(&em, "SELECT settled_until FROM ad_boxes", &[])
+            .unwrap();
+        assert_eq!(rows, [[Value::Bigint(0)]]);
+        let rows = query(&em, "SELECT bids.id FROM spend_ledger \
+                               JOIN bids ON bids.id = bid_id", &[])
+            .unwrap();
+        assert_eq!(rows, [[Value::Bigint(1)]]);
+    }
+
+    /// Puts a bid, with a spend ledger row, in a database at the first
+    /// migration, by an advertiser `advertiser_id` with creative 1.
+    fn insert_old_bid(em: &EntityManager, advertiser_id: i64) {
+        for sql in &[
+            "INSERT INTO advertisers (id, name) VALUES (1, 'A'), (2, 'B')",
+            "INSERT INTO creatives (id, advertiser_id, width, height, \
+             image_url, link_url, alt_text) VALUES (1, 1, 1, 1, '', '', '')",
+            "INSERT INTO spend_ledger (id, bid_id, shown_from, shown_until, \
+             rate, spent) VALUES (1, 1, 0, 0, 1, 0)"
+        ] {
+            execute(em, sql).unwrap();
+        }
+        query(em, "INSERT INTO bids (id, ad_box_id, advertiser_id, \
+                   creative_id, bid, expense_limit, spent, expiry, \
+                   placed_at) VALUES (1, 1, ?, 1, 1, 1, 0, 1, 0)",
+              &[Value::Bigint(advertiser_id)]).unwrap();
+    }
+
+    #[test]
+    fn upgrade_refuses_mismatched_bids() {
+        let uri = temp_sqlite_uri();
+        let em = Pool::new().em(&uri).unwrap();
+        transaction(&em, || apply_migration(&em, &MIGRATIONS[0])).unwrap();
+        execute(&em, "INSERT INTO publishers (id, name) VALUES (1, 'P')")
+            .unwrap();
+        execute(&em, "INSERT INTO ad_boxes (id, publisher_id, name, width, \
+                      height, min_bid, increment) \
+                      VALUES (1, 1, 'Box', 1, 1, 1, 1)").unwrap();
+        insert_old_bid(&em, 2);
+        drop(em);
+
+        match open_db(&uri) {
+            Err(Error::Migration { step: 4, cause }) => match *cause {
+                Error::DanglingReference { table } => {
+                    assert_eq!(table, "bids");
+                },
+                e => panic!("wrong error: {}", e)
+            },
+            Err(e) => panic!("wrong error: {}", e),
+            Ok(_) => panic!("migrated a bid with someone else's creative")
+        }
+        // The migrations before it stuck.
+        let em = Pool::new().em(&uri).unwrap();
+        assert_eq!(hash_schema(em.get_all_tables().unwrap()),
+                   MIGRATIONS[4].from);
+    }
+
+    #[test]
+    fn bad_uri() {
+        match open_db("nonsense://") {
+            Err(Error::Connection(_)) => (),
+            Err(e) => panic!("wrong error: {}", e),
+            Ok(_) => panic!("opened a nonsense URI")
+        }
+    }
+
+    #[test]
+    fn unknown_schema() {
+        let uri = temp_sqlite_uri();
+        let em = Pool::new().em(&uri).unwrap();
+        execute(&em, "CREATE TABLE mystery (id INTEGER PRIMARY KEY)")
+            .unwrap();
+        drop(em);
+        match open_db(&uri) {
+            Err(Error::UnknownSchema { fingerprint }) => {
+                assert_ne!(fingerprint, EMPTY_SCHEMA);
+                assert_ne!(fingerprint, current_schema());
+            },
+            Err(e) => panic!("wrong error: {}", e),
+            Ok(_) => panic!("opened a database with an unknown schema")
+        }
+    }
+
+    #[test]
+    fn in_memory() {
+        let DB(em) = open_db(IN_MEMORY).unwrap();
+        assert_eq!(hash_schema(em.get_all_tables().unwrap()),
+                   current_schema());
+        execute(&em, "INSERT INTO advertisers (id, name) VALUES (1, 'A')")
+            .unwrap();
+        assert_eq!(query(&em, "SELECT id FROM advertisers", &[]).unwrap(),
+                   [[Value::Bigint(1)]]);
+
+        // A new one's empty.
+        let DB(other) = open_memory_db().unwrap();
+        assert_eq!(query(&other, "SELECT id FROM advertisers", &[])
+                       .unwrap(), Vec::<Vec<Value>>::new());
+    }
+
+    /// SQLite's temporary files are unlinked as soon as they're opened,
+    /// so the only trace of them is the file descriptor.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn in_memory_stays_off_disk() {
+        let temp_files = || std::fs::read_dir("/proc/self/fd").unwrap()
+            .filter_map(|fd| std::fs::read_link(fd.unwrap().path()).ok())
+            .filter(|path| path.to_string_lossy().contains("etilqs_"))
+            .count();
+        let DB(em) = open_memory_db().unwrap();
+        // About 10MB: far more than SQLite caches by default.
+        execute(&em, "INSERT INTO advertisers (name) \
+                      WITH RECURSIVE n(i) AS \
+                      (SELECT 1 UNION ALL SELECT i + 1 FROM n \
+                       WHERE i < 2500) \
+                      SELECT hex(zeroblob(2000)) FROM n").unwrap();
+        assert_eq!(query(&em, "SELECT count(*) FROM advertisers", &[])
+                       .unwrap(), [[Value::Bigint(2500)]]);
+        assert_eq!(temp_files(), 0);
+    }
+
+    #[test]
+    fn nested_transactions() {
+        let db = open_memory_db().unwrap();
+        let names = || -> Vec<_> {
+            db.advertisers().unwrap().into_iter().map(|(_, name)| name)
+              .collect()
+        };
+        db.transaction(|| {
+            db.insert_advertiser("Kept")?;
+            let inner = db.transaction(|| {
+                db.insert_advertiser("Rolled back")?;
+                Err::<(), _>(Error::NotEmpty)
+            });
+            assert!(inner.is_err());
+            Ok::<_, Error>(())
+        }).unwrap();
+        assert_eq!(names(), ["Kept"]);
+
+        let outer = db.transaction(|| {
+            db.transaction(|| db.insert_advertiser("Committed inside"))?;
+            Err::<(), _>(Error::NotEmpty)
+        });
+        assert!(outer.is_err());
+        assert_eq!(names(), ["Kept"]);
+    }
+
+    #[test]
+    fn sql_values() {
+        assert_eq!(from_sql::<u64>(&Value::Int(5)).unwrap(), 5);
+        assert_eq!(from_sql::<Token>(&Value::Int(5)).unwrap(),
+                   Token::from(5));
+        match from_sql::<u64>(&Value::Smallint(-1)) {
+            Err(Error::Conversion(ConvertValueError::OutOfRange)) => (),
+            x => panic!("expected an out-of-range error, got {:?}", x)
+        }
+        match from_sql::<String>(&Value::Bigint(5)) {
+            Err(Error::NotText) => (),
+            x => panic!("expected a non-text error, got {:?}", x)
+        }
+        assert_eq!(from_sql::<Option<String>>(&Value::Nil).unwrap(), None);
+    }
+
+    #[test]
+    fn foreign_keys_enforced() {
+        let DB(em) = open_memory_db().unwrap();
+        match execute(&em, "INSERT INTO creatives
+                      (advertiser_id, width, height,
+                       image_url, link_url, alt_text)
+                      VALUES (1, 468, 60, '', '', '')")
+        {
+            Err(Error::ConstraintViolation(_)) => (),
+            Err(e) => panic!("wrong error: {}", e),
+            Ok(()) => panic!("inserted a creative with no advertiser")
+        }
+    }
+
+    #[test]
+    fn only_constraints_are_constraint_violations() {
+        let DB(em) = open_memory_db().unwrap();
+        match execute(&em, "SELECT * FROM \"constraint failed\"") {
+            Err(Error::Query(e)) => {
+                assert!(e.to_string().contains("constraint failed"));
+            },
+            x => panic!("expected a query error, got {:?}", x)
+        }
     }
 }
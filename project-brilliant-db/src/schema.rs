@@ -0,0 +1,411 @@
+use rustorm::{
+    table::{Table, TableKey},
+    column::{Capacity, ColumnConstraint, Literal}
+};
+#[allow(deprecated)]
+use core::hash::{SipHasher, Hash, Hasher};
+use std::fmt;
+
+/// A description of a database's schema, normalised so that the same
+/// schema is always described the same way: tables, columns, constraints
+/// and key columns are sorted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schema {
+    pub tables: Vec<TableSchema>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableSchema {
+    pub name: String,
+    pub is_view: bool,
+    pub columns: Vec<ColumnSchema>,
+    pub keys: Vec<KeySchema>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnSchema {
+    pub name: String,
+    /// The name of rustorm's `SqlType` variant, like `Int` or `Text`.
+    pub sql_type: String,
+    pub capacity: Option<Capacity>,
+    pub constraints: Vec<ColumnConstraint>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeySchema {
+    pub kind: KeyKind,
+    pub name: Option<String>,
+    pub columns: Vec<String>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyKind {
+    Primary,
+    Unique,
+    Index,
+    /// References `columns` of `table`.
+    Foreign { table: String, columns: Vec<String> }
+}
+
+/// One way a schema differs from the one it was expected to be.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaDifference {
+    MissingTable(String),
+    UnexpectedTable(String),
+    /// `table` is a view when it should be a table, or vice versa.
+    WrongKind { table: String, expected_view: bool },
+    MissingColumn { table: String, column: ColumnSchema },
+    UnexpectedColumn { table: String, column: ColumnSchema },
+    ChangedColumn {
+        table: String,
+        expected: ColumnSchema,
+        found: ColumnSchema
+    },
+    MissingKey { table: String, key: KeySchema },
+    UnexpectedKey { table: String, key: KeySchema }
+}
+
+impl From<Vec<Table>> for Schema {
+    fn from(mut tables: Vec<Table>) -> Schema {
+        tables.sort_unstable_by_key(Table::complete_name);
+        Schema { tables: tables.into_iter().map(TableSchema::from).collect() }
+    }
+}
+
+impl From<Table> for TableSchema {
+    fn from(table: Table) -> TableSchema {
+        use ColumnConstraint::*;
+        use TableKey::*;
+
+        let mut columns = table.columns;
+        columns.sort_unstable_by(|a, b| a.name.name.cmp(&b.name.name));
+        let columns = columns.into_iter().map(|column| {
+            let spec = column.specification;
+            let mut constraints = spec.constraints;
+            constraints.sort_unstable_by_key(|x| match x {
+                NotNull => 0,
+                DefaultValue(_) => 1,
+                AutoIncrement => 2
+            });
+            ColumnSchema {
+                name: column.name.name,
+                // SqlType::name only knows text types; the variant name is
+                // as stable, and covers everything.
+                sql_type: format!("{:?}", spec.sql_type),
+                capacity: spec.capacity,
+                constraints
+            }
+        }).collect();
+
+        let mut keys = table.table_key;
+        keys.sort_unstable_by(|a, b| match a {
+            PrimaryKey(key) => (0, &key.name),
+            UniqueKey(key) => (1, &key.name),
+            Key(key) => (2, &key.name),
+            ForeignKey(fk) => (3, &fk.name)
+        }.cmp(&match b {
+            PrimaryKey(key) => (0, &key.name),
+            UniqueKey(key) => (1, &key.name),
+            Key(key) => (2, &key.name),
+            ForeignKey(fk) => (3, &fk.name)
+        }));
+        let names = |columns: Vec<rustorm::ColumnName>| {
+            let mut names: Vec<_> = columns.into_iter()
+                                           .map(|x| x.name)
+                                           .collect();
+            names.sort_unstable();
+            names
+        };
+        let keys = keys.into_iter().map(|tk| match tk {
+            ForeignKey(fk) => KeySchema {
+                kind: KeyKind::Foreign {
+                    table: fk.foreign_table.name,
+                    columns: names(fk.referred_columns)
+                },
+                name: fk.name,
+                columns: names(fk.columns)
+            },
+            PrimaryKey(key) => KeySchema {
+                kind: KeyKind::Primary,
+                name: key.name,
+                columns: names(key.columns)
+            },
+            UniqueKey(key) => KeySchema {
+                kind: KeyKind::Unique,
+                name: key.name,
+                columns: names(key.columns)
+            },
+            Key(key) => KeySchema {
+                kind: KeyKind::Index,
+                name: key.name,
+                columns: names(key.columns)
+            }
+        }).collect();
+
+        TableSchema {
+            name: table.name.name,
+            is_view: table.is_view,
+            columns,
+            keys
+        }
+    }
+}
+
+impl Schema {
+    /// A fingerprint of the schema, as stored in the migration list.
+    // SipHasher is deprecated, but unlike DefaultHasher its output is
+    // guaranteed not to change between releases, and these hashes are
+    // stored. The order things are hashed in mustn't change either.
+    #[allow(deprecated)]
+    pub fn fingerprint(&self) -> u64 {
+        use Capacity::*;
+        use ColumnConstraint::*;
+        use Literal::*;
+
+        let mut hasher = SipHasher::new();
+        let h = &mut hasher;
+
+        for table in &self.tables {
+            table.name.hash(h);
+            for column in &table.columns {
+                column.name.hash(h);
+                column.sql_type.hash(h);
+                match column.capacity {
+                    Some(Limit(x)) => {
+                        "Limit".hash(h);
+                        x.hash(h);
+                    },
+                    Some(Range(x, y)) => {
+                        "Range".hash(h);
+                        x.hash(h);
+                        y.hash(h);
+                    },
+                    None => { "None".hash(h); }
+                };
+                for constraint in &column.constraints {
+                    match constraint {
+                        NotNull => "NotNull".hash(h),
+                        DefaultValue(x) => {
+                            "DefaultValue".hash(h);
+                            match x {
+                                Bool(x) => { "Bool".hash(h); x.hash(h) },
+                                Null => "Null".hash(h),
+                                Integer(x) => {
+                                    "Integer".hash(h);
+                                    x.hash(h)
+                                },
+                                Double(x) => {
+                                    "Double".hash(h);
+                                    x.to_bits().hash(h)
+                                },
+                                UuidGenerateV4 => "UuidGenerateV4".hash(h),
+                                Uuid(x) => {
+                                    "Uuid".hash(h);
+                                    x.as_bytes().hash(h)
+                                },
+                                String(x) => {"String".hash(h); x.hash(h) },
+                                Blob(x) => {"Blob".hash(h); x.hash(h) },
+                                CurrentTime => "CurrentTime".hash(h),
+                                CurrentDate => "CurrentDate".hash(h),
+                                CurrentTimestamp => {
+                                    "CurrentTimestamp".hash(h)
+                                },
+                                ArrayInt(x) => {
+                                    "ArrayInt".hash(h);
+                                    x.hash(h)
+                                },
+                                ArrayFloat(x) => {
+                                    "ArrayFloat".hash(h);
+                                    for double in x {
+                                        double.to_bits().hash(h);
+                                    }
+                                },
+                                ArrayString(x) => {
+                                    "ArrayString".hash(h);
+                                    x.hash(h)
+                                }
+                            }
+                        },
+                        AutoIncrement => "AutoIncrement".hash(h)
+                    }
+                }
+            }
+            table.is_view.hash(h);
+            for key in &table.keys {
+                match &key.kind {
+                    KeyKind::Foreign { table, columns } => {
+                        "ForeignKey".hash(h);
+                        key.name.hash(h);
+                        for x in &key.columns { x.hash(h) };
+                        table.hash(h);
+                        for x in columns { x.hash(h) };
+                    },
+                    kind => {
+                        match kind {
+                            KeyKind::Primary => "PrimaryKey".hash(h),
+                            KeyKind::Unique => "UniqueKey".hash(h),
+                            _ => "Key".hash(h)
+                        };
+                        key.name.hash(h);
+                        for x in &key.columns { x.hash(h); }
+                    }
+                };
+            }
+        };
+        h.finish()
+    }
+
+    /// Everything about `found` that isn't as this schema expects, table by
+    /// table.
+    pub fn diff(&self, found: &Schema) -> Vec<SchemaDifference> {
+        use SchemaDifference::*;
+
+        let mut differences = Vec::new();
+        for expected in &self.tables {
+            let table = match found.table(&expected.name) {
+                Some(table) => table,
+                None => {
+                    differences.push(MissingTable(expected.name.clone()));
+                    continue;
+                }
+            };
+            let name = || expected.name.clone();
+            if table.is_view != expected.is_view {
+                differences.push(WrongKind {
+                    table: name(),
+                    expected_view: expected.is_view
+                });
+            }
+            for column in &expected.columns {
+                match table.column(&column.name) {
+                    None => differences.push(MissingColumn {
+                        table: name(),
+                        column: column.clone()
+                    }),
+                    Some(x) if x != column => differences.push(ChangedColumn {
+                        table: name(),
+                        expected: column.clone(),
+                        found: x.clone()
+                    }),
+                    Some(_) => ()
+                }
+            }
+            for column in &table.columns {
+                if expected.column(&column.name).is_none() {
+                    differences.push(UnexpectedColumn {
+                        table: name(),
+                        column: column.clone()
+                    });
+                }
+            }
+            for key in &expected.keys {
+                if !table.keys.contains(key) {
+                    differences.push(MissingKey {
+                        table: name(),
+                        key: key.clone()
+                    });
+                }
+            }
+            for key in &table.keys {
+                if !expected.keys.contains(key) {
+                    differences.push(UnexpectedKey {
+                        table: name(),
+                        key: key.clone()
+                    });
+                }
+            }
+        }
+        for table in &found.tables {
+            if self.table(&table.name).is_none() {
+                differences.push(UnexpectedTable(table.name.clone()));
+            }
+        }
+        differences
+    }
+
+    pub fn table(&self, name: &str) -> Option<&TableSchema> {
+        self.tables.iter().find(|table| table.name == name)
+    }
+}
+
+impl TableSchema {
+    pub fn column(&self, name: &str) -> Option<&ColumnSchema> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+}
+
+impl fmt::Display for ColumnSchema {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.sql_type)?;
+        match self.capacity {
+            Some(Capacity::Limit(x)) => write!(f, "({})", x)?,
+            Some(Capacity::Range(x, y)) => write!(f, "({}, {})", x, y)?,
+            None => ()
+        }
+        for constraint in &self.constraints {
+            match constraint {
+                ColumnConstraint::NotNull => write!(f, " NOT NULL")?,
+                ColumnConstraint::DefaultValue(x) => {
+                    write!(f, " DEFAULT {:?}", x)?
+                },
+                ColumnConstraint::AutoIncrement => {
+                    write!(f, " AUTOINCREMENT")?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for KeySchema {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self.kind {
+            KeyKind::Primary => "PRIMARY KEY",
+            KeyKind::Unique => "UNIQUE",
+            KeyKind::Index => "INDEX",
+            KeyKind::Foreign { .. } => "FOREIGN KEY"
+        })?;
+        if let Some(name) = &self.name {
+            write!(f, " {}", name)?;
+        }
+        write!(f, " ({})", self.columns.join(", "))?;
+        if let KeyKind::Foreign { table, columns } = &self.kind {
+            write!(f, " REFERENCES {} ({})", table, columns.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SchemaDifference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use SchemaDifference::*;
+        match self {
+            MissingTable(table) => write!(f, "table {} is missing", table),
+            UnexpectedTable(table) => {
+                write!(f, "table {} isn't expected", table)
+            },
+            WrongKind { table, expected_view: true } => {
+                write!(f, "{} should be a view, but is a table", table)
+            },
+            WrongKind { table, expected_view: false } => {
+                write!(f, "{} should be a table, but is a view", table)
+            },
+            MissingColumn { table, column } => write!(
+                f, "table {} is missing column `{}`", table, column
+            ),
+            UnexpectedColumn { table, column } => write!(
+                f, "table {} has unexpected column `{}`", table, column
+            ),
+            ChangedColumn { table, expected, found } => write!(
+                f, "table {} should have column `{}`, but has `{}`",
+                table, expected, found
+            ),
+            MissingKey { table, key } => write!(
+                f, "table {} is missing `{}`", table, key
+            ),
+            UnexpectedKey { table, key } => write!(
+                f, "table {} has unexpected `{}`", table, key
+            )
+        }
+    }
+}
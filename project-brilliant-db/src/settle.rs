@@ -0,0 +1,377 @@
+use crate::{
+    DB, Error, AdBoxId, AdvertiserId, PublisherId,
+    query, transaction, to_sql, from_sql,
+    ledger::record_payment
+};
+use infinite_auction::{AuctionParams, AuctionState, Bid, BidId, Segment};
+use project_brilliant_utilities::{Currency, Timestamp, Token};
+use std::collections::HashMap;
+
+/// Something that happens to a bid partway through a settlement.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Event {
+    // Cancellations go first, so a bid cancelled and replaced at the same
+    // moment isn't up against itself.
+    Cancel,
+    Place
+}
+
+/// Who gets paid when an ad box's bids are charged.
+struct Payee {
+    publisher: PublisherId,
+    fee_percent: u64,
+    /// Who's paying, for each bid.
+    advertisers: HashMap<BidId, AdvertiserId>
+}
+
+impl DB {
+    /// Run an ad box's auction from where it was last settled up to `now`,
+    /// and record what was shown, in one transaction: each segment goes in
+    /// the spend ledger, what it cost is added to the winning bid's spend,
+    /// and the ad box is marked as settled up to `now`.
+    ///
+    /// Bids placed or cancelled since the last settlement join or leave the
+    /// auction when they were placed or cancelled. Returns the segments
+    /// shown since the last settlement, if any.
+    pub fn settle_ad_box(
+        &self, ad_box: AdBoxId, now: Timestamp
+    ) -> Result<Vec<Segment<BidId>>, Error> {
+        transaction(&self.0, || self.settle(ad_box, now))
+    }
+
+    /// The rules of an ad box's auction.
+    pub fn auction_params(
+        &self, ad_box: AdBoxId
+    ) -> Result<AuctionParams, Error> {
+        let rows = query(&self.0, "SELECT increment, min_bid FROM ad_boxes \
+                                   WHERE id = ?", &[to_sql(ad_box)?])?;
+        let row = rows.first().ok_or(Error::UnknownAdBox(ad_box))?;
+        Ok(AuctionParams {
+            increment: from_sql(&row[0])?,
+            min_bid: from_sql(&row[1])?
+        })
+    }
+
+    /// Work out what an ad box will show from where it was last settled,
+    /// if nobody changes their bids. Bids placed after that are left out.
+    /// Nothing is recorded.
+    pub fn forecast(
+        &self, ad_box: AdBoxId
+    ) -> Result<Vec<Segment<BidId>>, Error> {
+        let mut state = self.auction_state(ad_box)?;
+        let end = Timestamp::max_value();
+        let mut segments = state.advance_to(end).map_err(Error::Auction)?;
+        // If the auction went wrong, it's the next call that says so.
+        segments.extend(state.advance_to(end).map_err(Error::Auction)?);
+        Ok(segments)
+    }
+
+    /// The bid an ad box is showing, as of where it was last settled.
+    pub fn current_winner(
+        &self, ad_box: AdBoxId
+    ) -> Result<Option<BidId>, Error> {
+        let mut state = self.auction_state(ad_box)?;
+        let winner = state.current_winner().map_err(Error::Auction)?;
+        Ok(winner.map(|(id, _)| id))
+    }
+
+    /// When the segment an ad box is showing, as of where it was last
+    /// settled, ends: when it next needs settling, unless its bids change.
+    pub fn segment_end(
+        &self, ad_box: AdBoxId
+    ) -> Result<Option<Timestamp>, Error> {
+        self.auction_state(ad_box)?.segment_end().map_err(Error::Auction)
+    }
+
+    /// An ad box's auction, as of where it was last settled.
+    fn auction_state(
+        &self, ad_box: AdBoxId
+    ) -> Result<AuctionState<BidId>, Error> {
+        let params = self.auction_params(ad_box)?;
+        let settled_until = self.settled_until(ad_box)?;
+        let mut state = AuctionState::new(
+            params.increment, params.min_bid, settled_until
+        );
+        for stored in self.bids_for_ad_box(ad_box)? {
+            if stored.placed_at <= settled_until {
+                state.place_bid(stored.id, Bid {
+                    bid: stored.bid.bid,
+                    expense_limit: stored.remaining(),
+                    expiry: stored.bid.expiry,
+                    data: stored.id
+                });
+            }
+        }
+        Ok(state)
+    }
+
+    fn settle(
+        &self, ad_box: AdBoxId, now: Timestamp
+    ) -> Result<Vec<Segment<BidId>>, Error> {
+        let rows = query(&self.0, "SELECT min_bid, increment, settled_until, \
+                                   publisher_id, fee_percent \
+                                   FROM ad_boxes WHERE id = ?",
+                         &[to_sql(ad_box)?])?;
+        let row = rows.first().ok_or(Error::UnknownAdBox(ad_box))?;
+        let min_bid: Currency = from_sql(&row[0])?;
+        let increment: Currency = from_sql(&row[1])?;
+        let settled_until: Timestamp = from_sql(&row[2])?;
+        let mut payee = Payee {
+            publisher: from_sql(&row[3])?,
+            fee_percent: from_sql(&row[4])?,
+            advertisers: HashMap::new()
+        };
+        if now <= settled_until {
+            return Ok(Vec::new());
+        }
+
+        let mut bids = HashMap::new();
+        let mut events = Vec::new();
+        for row in query(&self.0, "SELECT id, bid, expense_limit, spent, \
+                                   expiry, placed_at, cancelled_at, \
+                                   advertiser_id \
+                                   FROM bids WHERE ad_box_id = ? \
+                                   AND placed_at < ? AND (cancelled_at IS \
+                                   NULL OR cancelled_at > ?)", &[
+            to_sql(ad_box)?, to_sql(now)?, to_sql(settled_until)?
+        ])? {
+            let id: BidId = from_sql(&row[0])?;
+            let limit: Token = from_sql(&row[2])?;
+            let placed_at: Timestamp = from_sql(&row[5])?;
+            bids.insert(id, Bid {
+                bid: from_sql(&row[1])?,
+                expense_limit: limit.saturating_sub(from_sql(&row[3])?),
+                expiry: from_sql(&row[4])?,
+                data: id
+            });
+            payee.advertisers.insert(id, from_sql(&row[7])?);
+            events.push((placed_at.max(settled_until), Event::Place, id));
+            match from_sql(&row[6])? {
+                Some(cancelled_at) if cancelled_at < now => {
+                    events.push((cancelled_at, Event::Cancel, id));
+                },
+                _ => ()
+            }
+        }
+        events.sort_unstable();
+
+        let mut state = AuctionState::new(increment, min_bid, settled_until);
+        let mut segments = Vec::new();
+        for (at, event, id) in events {
+            segments.extend(state.advance_to(at).map_err(Error::Auction)?);
+            match event {
+                Event::Place => { state.place_bid(id, bids[&id]); },
+                Event::Cancel => { state.cancel_bid(id); }
+            }
+        }
+        segments.extend(state.advance_to(now).map_err(Error::Auction)?);
+        // If the auction went wrong, it's the next call that says so.
+        segments.extend(state.advance_to(now).map_err(Error::Auction)?);
+
+        self.record_segments(ad_box, &payee, &segments)?;
+        query(&self.0, "UPDATE ad_boxes SET settled_until = ? WHERE id = ?",
+              &[to_sql(now)?, to_sql(ad_box)?])?;
+        Ok(segments)
+    }
+
+    /// Add segments to the spend ledger, charge the bids for them, and pay
+    /// the publisher. A segment that carries straight on from the last one,
+    /// with the same bid at the same rate, is merged into it.
+    fn record_segments(
+        &self, ad_box: AdBoxId, payee: &Payee, segments: &[Segment<BidId>]
+    ) -> Result<(), Error> {
+        let rows = query(&self.0, "SELECT spend_ledger.id, bid_id, \
+                                   shown_until, rate FROM spend_ledger \
+                                   JOIN bids ON bids.id = bid_id \
+                                   WHERE ad_box_id = ? \
+                                   ORDER BY shown_until DESC LIMIT 1",
+                         &[to_sql(ad_box)?])?;
+        let mut last: Option<(u64, BidId, Timestamp, Currency)> =
+            match rows.first() {
+                Some(row) => Some((
+                    from_sql(&row[0])?, from_sql(&row[1])?,
+                    from_sql(&row[2])?, from_sql(&row[3])?
+                )),
+                None => None
+            };
+
+        for segment in segments {
+            let spend = match last {
+                Some((id, bid, end, rate)) if bid == segment.winner
+                                           && end == segment.start
+                                           && rate == segment.rate => {
+                    query(&self.0, "UPDATE spend_ledger SET shown_until = ?, \
+                                    spent = spent + ? WHERE id = ?", &[
+                        to_sql(segment.end)?, to_sql(segment.spent)?,
+                        to_sql(id)?
+                    ])?;
+                    id
+                },
+                _ => {
+                    query(&self.0, "INSERT INTO spend_ledger (bid_id, \
+                                    shown_from, shown_until, rate, spent) \
+                                    VALUES (?, ?, ?, ?, ?)", &[
+                        to_sql(segment.winner)?, to_sql(segment.start)?,
+                        to_sql(segment.end)?, to_sql(segment.rate)?,
+                        to_sql(segment.spent)?
+                    ])?;
+                    let rows = query(&self.0, "SELECT last_insert_rowid()",
+                                     &[])?;
+                    from_sql(&rows[0][0])?
+                }
+            };
+            last = Some((spend, segment.winner, segment.end, segment.rate));
+            query(&self.0, "UPDATE bids SET spent = spent + ? WHERE id = ?",
+                  &[to_sql(segment.spent)?, to_sql(segment.winner)?])?;
+            record_payment(
+                &self.0, spend, payee.advertisers[&segment.winner],
+                payee.publisher, payee.fee_percent, segment.spent
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{query, testing::{ad_box_db, at, bid}};
+    use infinite_auction::Amendment;
+    use rustorm::Value;
+
+    fn ledger(db: &DB) -> Vec<Vec<Value>> {
+        query(&db.0, "SELECT bid_id, shown_from, shown_until, rate, spent \
+                      FROM spend_ledger ORDER BY shown_from", &[]).unwrap()
+    }
+
+    fn row(bid: BidId, from: u64, until: u64, rate: u64, spent: u64)
+        -> Vec<Value>
+    {
+        [bid.0, from, until, rate, spent].iter()
+            .map(|&x| Value::Bigint(x as i64)).collect()
+    }
+
+    #[test]
+    fn settle_incrementally() {
+        let (db, ad_box, advert) = ad_box_db();
+        let bid = bid(advert, 5, 1000, 500);
+        let id = db.insert_bid(ad_box, &bid, at(0)).unwrap();
+
+        assert_eq!(db.settle_ad_box(ad_box, at(100)).unwrap(), [Segment {
+            start: at(0),
+            end: at(100),
+            winner: id,
+            rate: Currency::from(1),
+            spent: Token::from(100),
+            runner_up: None
+        }]);
+        assert_eq!(db.settle_ad_box(ad_box, at(50)).unwrap(), []);
+        assert_eq!(db.settle_ad_box(ad_box, at(250)).unwrap().len(), 1);
+        assert_eq!(ledger(&db), [row(id, 0, 250, 1, 250)]);
+        let stored = db.get_bid(id).unwrap().unwrap();
+        assert_eq!(stored.spent, Token::from(250));
+        assert_eq!(stored.bid.expense_limit, Token::from(1000));
+        assert_eq!(stored.remaining(), Token::from(750));
+
+        // Past its expiry
+        db.settle_ad_box(ad_box, at(1000)).unwrap();
+        assert_eq!(ledger(&db), [row(id, 0, 500, 1, 500)]);
+    }
+
+    #[test]
+    fn settle_placed_and_cancelled() {
+        let (db, ad_box, advert) = ad_box_db();
+        let bid = bid(advert, 5, 1000, 500);
+        let low = db.insert_bid(ad_box, &bid, at(0)).unwrap();
+        let high = db.insert_bid(ad_box, &Bid {
+            bid: Currency::from(10),
+            ..bid
+        }, at(50)).unwrap();
+        db.cancel_bid(high, at(80)).unwrap();
+        // Placed after the settlement
+        db.insert_bid(ad_box, &bid, at(200)).unwrap();
+
+        let segments = db.settle_ad_box(ad_box, at(100)).unwrap();
+        assert_eq!(segments.len(), 3);
+        assert_eq!(ledger(&db), [
+            row(low, 0, 50, 1, 50),
+            row(high, 50, 80, 6, 180),
+            row(low, 80, 100, 1, 20)
+        ]);
+        let spent = query(&db.0, "SELECT spent FROM bids ORDER BY id", &[])
+            .unwrap();
+        assert_eq!(spent, [[Value::Bigint(70)], [Value::Bigint(180)],
+                           [Value::Bigint(0)]]);
+    }
+
+    #[test]
+    fn amend_after_showing() {
+        let (db, ad_box, advert) = ad_box_db();
+        let low = db.insert_bid(ad_box, &bid(advert, 5, 1000, 500), at(0))
+                    .unwrap();
+        let high = db.insert_bid(ad_box, &bid(advert, 10, 1000, 500), at(0))
+                     .unwrap();
+        // Until then, the high bid pays 6 a second to outbid the low one.
+        assert!(db.amend_bid(low, Amendment::Bid(Currency::from(2)), at(50))
+                  .unwrap());
+        assert_eq!(db.settled_until(ad_box).unwrap(), at(50));
+        db.settle_ad_box(ad_box, at(100)).unwrap();
+        assert_eq!(ledger(&db), [
+            row(high, 0, 50, 6, 300),
+            row(high, 50, 100, 3, 150)
+        ]);
+    }
+
+    #[test]
+    fn forecast() {
+        let (db, ad_box, advert) = ad_box_db();
+        let bid = bid(advert, 5, 1000, 500);
+        let low = db.insert_bid(ad_box, &bid, at(0)).unwrap();
+        let high = db.insert_bid(ad_box, &Bid {
+            bid: Currency::from(10),
+            expense_limit: Token::from(60),
+            ..bid
+        }, at(0)).unwrap();
+        // Placed after the settlement
+        db.insert_bid(ad_box, &bid, at(200)).unwrap();
+        db.settle_ad_box(ad_box, at(4)).unwrap();
+
+        let segment = |winner, start, end, rate, spent, runner_up| Segment {
+            start: at(start),
+            end: at(end),
+            winner,
+            rate: Currency::from(rate),
+            spent: Token::from(spent),
+            runner_up
+        };
+        assert_eq!(db.forecast(ad_box).unwrap(), [
+            segment(high, 4, 10, 6, 36, Some(low)),
+            segment(low, 10, 500, 1, 490, None)
+        ]);
+        // Forecasting doesn't charge anyone.
+        assert_eq!(db.get_bid(high).unwrap().unwrap().spent,
+                   Token::from(24));
+        assert_eq!(db.auction_params(ad_box).unwrap(), AuctionParams {
+            increment: Currency::from(1),
+            min_bid: Currency::from(1)
+        });
+        assert_eq!(db.current_winner(ad_box).unwrap(), Some(high));
+        assert_eq!(db.segment_end(ad_box).unwrap(), Some(at(10)));
+        db.settle_ad_box(ad_box, at(500)).unwrap();
+        assert_eq!(db.current_winner(ad_box).unwrap(), None);
+        assert_eq!(db.segment_end(ad_box).unwrap(), None);
+    }
+
+    #[test]
+    fn settle_unknown_ad_box() {
+        let (db, _, _) = ad_box_db();
+        match db.settle_ad_box(AdBoxId(2), at(100)) {
+            Err(Error::UnknownAdBox(AdBoxId(2))) => (),
+            x => panic!("expected an unknown ad box, got {:?}", x)
+        }
+        match db.forecast(AdBoxId(2)) {
+            Err(Error::UnknownAdBox(AdBoxId(2))) => (),
+            x => panic!("expected an unknown ad box, got {:?}", x)
+        }
+    }
+}
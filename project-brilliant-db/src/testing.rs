@@ -0,0 +1,76 @@
+//! Fixtures for tests, in this crate and the ones built on it. Everything
+//! here panics rather than returning errors.
+
+use crate::{open_memory_db, Ad, AdBox, AdBoxId, Advert, DB, PublisherId};
+use infinite_auction::Bid;
+use project_brilliant_utilities::{Currency, Second, Timestamp, Token, EPOCH};
+
+/// `seconds` after the epoch.
+pub fn at(seconds: u64) -> Timestamp {
+    EPOCH + Second::from(seconds)
+}
+
+/// A bid of `bid` for `advert`, which can spend `expense_limit` tokens
+/// until `at(expiry)`.
+pub fn bid(advert: Advert, bid: u64, expense_limit: u64, expiry: u64)
+    -> Bid<Advert>
+{
+    Bid {
+        bid: Currency::from(bid),
+        expense_limit: Token::from(expense_limit),
+        expiry: at(expiry),
+        data: advert
+    }
+}
+
+/// A database with one publisher, and one advertiser with one 120×600
+/// creative, ready for ad boxes.
+pub struct Seeded {
+    pub db: DB,
+    pub publisher: PublisherId,
+    pub advert: Advert
+}
+
+pub fn seeded() -> Seeded {
+    let db = open_memory_db().unwrap();
+    let publisher = db.insert_publisher("Publisher").unwrap();
+    let advertiser = db.insert_advertiser("Advertiser").unwrap();
+    let creative = db.insert_creative(advertiser, &Ad {
+        width: 120,
+        height: 600,
+        image_url: "https://example.com/ad.png".into(),
+        link_url: "https://example.com".into(),
+        alt_text: "An ad".into()
+    }).unwrap();
+    Seeded { db, publisher, advert: Advert { advertiser, creative } }
+}
+
+impl Seeded {
+    /// A 120×600 ad box of the publisher's, with a minimum bid and
+    /// increment of 1, no fee and no house ad, to adjust before adding it.
+    pub fn ad_box(&self) -> AdBox {
+        AdBox {
+            publisher: self.publisher,
+            name: "Sidebar".into(),
+            width: 120,
+            height: 600,
+            min_bid: Currency::from(1),
+            increment: Currency::from(1),
+            fee_percent: 0,
+            house_image_url: None,
+            house_link_url: None
+        }
+    }
+
+    pub fn add_ad_box(&self, ad_box: &AdBox) -> AdBoxId {
+        self.db.insert_ad_box(ad_box).unwrap()
+    }
+}
+
+/// A database with one ad box (with a minimum bid and increment of 1),
+/// and one advertiser with one creative.
+pub fn ad_box_db() -> (DB, AdBoxId, Advert) {
+    let seeded = seeded();
+    let ad_box = seeded.add_ad_box(&seeded.ad_box());
+    (seeded.db, ad_box, seeded.advert)
+}
@@ -0,0 +1,96 @@
+use crate::{DB, Error, AdBoxId, Advert, query, to_sql, from_sql};
+use infinite_auction::BidId;
+use project_brilliant_utilities::{Currency, Timestamp, Token};
+
+/// A stretch of time one bid's ad was shown in an ad box, as recorded in
+/// the spend ledger.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Showing {
+    pub bid: BidId,
+    pub advert: Advert,
+    pub start: Timestamp,
+    pub end: Timestamp,
+    /// What the bid was charged per second.
+    pub rate: Currency,
+    pub spent: Token
+}
+
+impl DB {
+    /// What an ad box was showing at `at`, if anything. Only settled time
+    /// is recorded, so this is always `None` from [`settled_until`] on.
+    ///
+    ///   [`settled_until`]: #method.settled_until
+    pub fn shown_at(
+        &self, ad_box: AdBoxId, at: Timestamp
+    ) -> Result<Option<Showing>, Error> {
+        let rows = query(&self.0, "SELECT bid_id, advertiser_id, \
+                                   creative_id, shown_from, shown_until, \
+                                   rate, spend_ledger.spent \
+                                   FROM spend_ledger \
+                                   JOIN bids ON bids.id = bid_id \
+                                   WHERE ad_box_id = ? \
+                                   AND shown_from <= ? AND shown_until > ?",
+                         &[to_sql(ad_box)?, to_sql(at)?, to_sql(at)?])?;
+        rows.first().map(|row| Ok(Showing {
+            bid: from_sql(&row[0])?,
+            advert: Advert {
+                advertiser: from_sql(&row[1])?,
+                creative: from_sql(&row[2])?
+            },
+            start: from_sql(&row[3])?,
+            end: from_sql(&row[4])?,
+            rate: from_sql(&row[5])?,
+            spent: from_sql(&row[6])?
+        })).transpose()
+    }
+
+    /// How far an ad box's auction has been settled.
+    pub fn settled_until(&self, ad_box: AdBoxId) -> Result<Timestamp, Error> {
+        let rows = query(&self.0, "SELECT settled_until FROM ad_boxes \
+                                   WHERE id = ?", &[to_sql(ad_box)?])?;
+        from_sql(&rows.first().ok_or(Error::UnknownAdBox(ad_box))?[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ad_box_db, at, bid};
+    use infinite_auction::Bid;
+
+    #[test]
+    fn point_in_time() {
+        let (db, ad_box, advert) = ad_box_db();
+        let bid = bid(advert, 5, 1000, 500);
+        let low = db.insert_bid(ad_box, &bid, at(10)).unwrap();
+        let high = db.insert_bid(ad_box, &Bid {
+            bid: Currency::from(10),
+            ..bid
+        }, at(50)).unwrap();
+        db.cancel_bid(high, at(80)).unwrap();
+        db.settle_ad_box(ad_box, at(100)).unwrap();
+        assert_eq!(db.settled_until(ad_box).unwrap(), at(100));
+
+        assert_eq!(db.shown_at(ad_box, at(5)).unwrap(), None);
+        let showing = |bid, start, end, rate, spent| Some(Showing {
+            bid,
+            advert,
+            start: at(start),
+            end: at(end),
+            rate: Currency::from(rate),
+            spent: Token::from(spent)
+        });
+        assert_eq!(db.shown_at(ad_box, at(10)).unwrap(),
+                   showing(low, 10, 50, 1, 40));
+        assert_eq!(db.shown_at(ad_box, at(50)).unwrap(),
+                   showing(high, 50, 80, 6, 180));
+        assert_eq!(db.shown_at(ad_box, at(99)).unwrap(),
+                   showing(low, 80, 100, 1, 20));
+        assert_eq!(db.shown_at(ad_box, at(100)).unwrap(), None);
+
+        match db.settled_until(AdBoxId(2)) {
+            Err(Error::UnknownAdBox(AdBoxId(2))) => (),
+            x => panic!("expected an unknown ad box, got {:?}", x)
+        }
+    }
+}
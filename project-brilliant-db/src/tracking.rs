@@ -0,0 +1,160 @@
+use crate::{DB, Error, AdBoxId, query, to_sql, from_sql};
+use infinite_auction::BidId;
+use project_brilliant_utilities::{Timestamp, Token};
+
+/// A click on an ad that was served.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Click {
+    pub impression: u64,
+    pub ad_box: AdBoxId,
+    /// The bid whose ad it was, or `None` for a house ad.
+    pub bid: Option<BidId>,
+    /// Where the ad linked to, when it was served.
+    pub link_url: String
+}
+
+/// What one of a bid's spend ledger rows bought.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SegmentStats {
+    pub spend: u64,
+    pub start: Timestamp,
+    pub end: Timestamp,
+    pub spent: Token,
+    pub impressions: u64,
+    pub clicks: u64
+}
+
+impl DB {
+    /// Record that an ad box served an ad at `at`: `bid`'s, or the house
+    /// ad if `None`. Returns the impression's token, for its click-through
+    /// link; tokens can't be guessed, so clicks can't be made up.
+    pub fn record_impression(
+        &self, ad_box: AdBoxId, bid: Option<BidId>, link_url: &str,
+        at: Timestamp
+    ) -> Result<String, Error> {
+        query(&self.0, "INSERT INTO impressions (ad_box_id, bid_id, \
+                        shown_at, link_url, token) \
+                        VALUES (?, ?, ?, ?, lower(hex(randomblob(16))))", &[
+            to_sql(ad_box)?, to_sql(bid)?, to_sql(at)?,
+            to_sql(link_url.to_owned())?
+        ])?;
+        let rows = query(&self.0, "SELECT token FROM impressions \
+                                   WHERE id = last_insert_rowid()", &[])?;
+        from_sql(&rows[0][0])
+    }
+
+    /// Record a click, at `at`, on the impression with this token. Returns
+    /// `None` if there isn't one.
+    pub fn record_click(
+        &self, token: &str, at: Timestamp
+    ) -> Result<Option<Click>, Error> {
+        let rows = query(&self.0, "SELECT id, ad_box_id, bid_id, link_url \
+                                   FROM impressions WHERE token = ?",
+                         &[to_sql(token.to_owned())?])?;
+        let click = match rows.first() {
+            Some(row) => Click {
+                impression: from_sql(&row[0])?,
+                ad_box: from_sql(&row[1])?,
+                bid: from_sql(&row[2])?,
+                link_url: from_sql(&row[3])?
+            },
+            None => return Ok(None)
+        };
+        query(&self.0, "INSERT INTO clicks (impression_id, clicked_at) \
+                        VALUES (?, ?)",
+              &[to_sql(click.impression)?, to_sql(at)?])?;
+        Ok(Some(click))
+    }
+
+    /// Each of a bid's spend ledger rows, oldest first, with how many times
+    /// its ad was served, and clicked, meanwhile. Impressions since the
+    /// bid's ad box was last settled aren't counted until it's settled
+    /// again.
+    pub fn segment_stats(
+        &self, bid: BidId
+    ) -> Result<Vec<SegmentStats>, Error> {
+        if query(&self.0, "SELECT 1 FROM bids WHERE id = ?",
+                 &[to_sql(bid)?])?.is_empty() {
+            return Err(Error::UnknownBid(bid));
+        }
+        let rows = query(&self.0, "SELECT spend_ledger.id, shown_from, \
+                                   shown_until, spent, \
+                                   COUNT(DISTINCT impressions.id), \
+                                   COUNT(clicks.id) \
+                                   FROM spend_ledger \
+                                   LEFT JOIN impressions \
+                                   ON impressions.bid_id = \
+                                   spend_ledger.bid_id \
+                                   AND shown_at >= shown_from \
+                                   AND shown_at < shown_until \
+                                   LEFT JOIN clicks \
+                                   ON impression_id = impressions.id \
+                                   WHERE spend_ledger.bid_id = ? \
+                                   GROUP BY spend_ledger.id \
+                                   ORDER BY shown_from", &[to_sql(bid)?])?;
+        rows.iter().map(|row| Ok(SegmentStats {
+            spend: from_sql(&row[0])?,
+            start: from_sql(&row[1])?,
+            end: from_sql(&row[2])?,
+            spent: from_sql(&row[3])?,
+            impressions: from_sql(&row[4])?,
+            clicks: from_sql(&row[5])?
+        })).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ad_box_db, at, bid};
+
+    #[test]
+    fn impressions_and_clicks() {
+        let (db, ad_box, advert) = ad_box_db();
+        let id = db.insert_bid(ad_box, &bid(advert, 5, 1000, 500), at(0))
+                   .unwrap();
+        let link = "https://example.com";
+        let house = db.record_impression(ad_box, None, "house", at(0))
+                      .unwrap();
+        let first = db.record_impression(ad_box, Some(id), link, at(10))
+                      .unwrap();
+        db.record_impression(ad_box, Some(id), link, at(60)).unwrap();
+        assert_eq!(first.len(), 32);
+        assert_ne!(first, house);
+        db.settle_ad_box(ad_box, at(100)).unwrap();
+        db.record_impression(ad_box, Some(id), link, at(150)).unwrap();
+
+        assert_eq!(db.record_click(&first, at(11)).unwrap(), Some(Click {
+            impression: 2,
+            ad_box,
+            bid: Some(id),
+            link_url: link.into()
+        }));
+        db.record_click(&first, at(12)).unwrap();
+        assert_eq!(db.record_click(&house, at(13)).unwrap().unwrap().bid,
+                   None);
+        assert_eq!(db.record_click("nonsense", at(14)).unwrap(), None);
+
+        let stats = SegmentStats {
+            spend: 1,
+            start: at(0),
+            end: at(100),
+            spent: Token::from(100),
+            impressions: 2,
+            clicks: 2
+        };
+        assert_eq!(db.segment_stats(id).unwrap(), [stats]);
+        // The same row, carried on
+        db.settle_ad_box(ad_box, at(200)).unwrap();
+        assert_eq!(db.segment_stats(id).unwrap(), [SegmentStats {
+            end: at(200),
+            spent: Token::from(200),
+            impressions: 3,
+            ..stats
+        }]);
+        match db.segment_stats(BidId(2)) {
+            Err(Error::UnknownBid(BidId(2))) => (),
+            x => panic!("expected an unknown bid, got {:?}", x)
+        }
+    }
+}
@@ -1,38 +1,421 @@
 #[macro_use]
 extern crate derive_more;
 
-use std::ops::{Mul, Div, Rem};
+use std::error::Error;
+use std::fmt;
+use std::ops::{Mul, Div, Rem, Add, AddAssign, Sub, SubAssign};
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+#[cfg(feature = "rustorm")]
+use rustorm_dao::{ToValue, Value};
+#[cfg(feature = "rustorm")]
+use std::convert::TryFrom;
 
 pub const SECONDS_PER_DAY: Second = Second(86_400);
+/// The Unix epoch.
+pub const EPOCH: Timestamp = Timestamp(0);
 
 type IntegerType = u64;
 
-#[derive(Copy, Clone, Debug, From, PartialEq, Eq, PartialOrd, Ord,
+#[derive(Copy, Clone, Debug, From, Into, PartialEq, Eq, PartialOrd, Ord,
          Add, AddAssign, Sub, SubAssign)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Second(IntegerType);
-#[derive(Copy, Clone, Debug, From, PartialEq, Eq, PartialOrd, Ord,
+#[derive(Copy, Clone, Debug, From, Into, PartialEq, Eq, PartialOrd, Ord,
          Add, AddAssign, Sub, SubAssign)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Currency(IntegerType);
-#[derive(Copy, Clone, Debug, From, PartialEq, Eq, PartialOrd, Ord,
+#[derive(Copy, Clone, Debug, From, Into, PartialEq, Eq, PartialOrd, Ord,
          Add, AddAssign, Sub, SubAssign)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Token(IntegerType);
+/// A point in time, in seconds since the Unix epoch. `Second` is a length
+/// of time; subtract two `Timestamp`s to get one.
+#[derive(Copy, Clone, Debug, From, Into, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Timestamp(IntegerType);
 
 impl Second {
     pub const fn max_value() -> Second {
-        Second(IntegerType::max_value())
+        Second(IntegerType::MAX)
     }
 }
 impl Currency {
     pub const fn max_value() -> Currency {
-        Currency(IntegerType::max_value())
+        Currency(IntegerType::MAX)
     }
 }
 impl Token {
     pub const fn max_value() -> Token {
-        Token(IntegerType::max_value())
+        Token(IntegerType::MAX)
+    }
+}
+impl Timestamp {
+    pub const fn max_value() -> Timestamp {
+        Timestamp(IntegerType::MAX)
+    }
+
+    pub fn since_epoch(self) -> Second {
+        Second(self.0)
+    }
+
+    pub fn checked_add(self, rhs: Second) -> Option<Timestamp> {
+        self.0.checked_add(rhs.0).map(Timestamp)
+    }
+    pub fn checked_sub(self, rhs: Second) -> Option<Timestamp> {
+        self.0.checked_sub(rhs.0).map(Timestamp)
+    }
+    pub fn saturating_add(self, rhs: Second) -> Timestamp {
+        Timestamp(self.0.saturating_add(rhs.0))
+    }
+    pub fn saturating_sub(self, rhs: Second) -> Timestamp {
+        Timestamp(self.0.saturating_sub(rhs.0))
+    }
+    /// How long it's been since `earlier`, or `None` if it's in the future.
+    pub fn checked_duration_since(self, earlier: Timestamp) -> Option<Second> {
+        self.0.checked_sub(earlier.0).map(Second)
+    }
+}
+
+impl Add<Second> for Timestamp {
+    type Output = Timestamp;
+    fn add(self: Timestamp, rhs: Second) -> Timestamp {
+        Timestamp(self.0 + rhs.0)
+    }
+}
+impl AddAssign<Second> for Timestamp {
+    fn add_assign(&mut self, rhs: Second) {
+        self.0 += rhs.0;
+    }
+}
+impl Sub<Second> for Timestamp {
+    type Output = Timestamp;
+    fn sub(self: Timestamp, rhs: Second) -> Timestamp {
+        Timestamp(self.0 - rhs.0)
+    }
+}
+impl SubAssign<Second> for Timestamp {
+    fn sub_assign(&mut self, rhs: Second) {
+        self.0 -= rhs.0;
+    }
+}
+impl Sub<Timestamp> for Timestamp {
+    type Output = Second;
+    fn sub(self: Timestamp, rhs: Timestamp) -> Second {
+        Second(self.0 - rhs.0)
+    }
+}
+
+/// Shown in dollars, like `$5.10`. Since a `Currency` is a rate, you
+/// probably want [`per_day`] when showing it to people.
+///
+///   [`per_day`]: #method.per_day
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(&format!("${}.{:02}", self.0 / 100, self.0 % 100))
+    }
+}
+
+/// Reads dollars, like `$5.10`, `5.10 USD` or just `5.1`.
+impl FromStr for Currency {
+    type Err = ParseCurrencyError;
+    fn from_str(s: &str) -> Result<Currency, ParseCurrencyError> {
+        let s = s.trim();
+        let amount = if let Some(amount) = s.strip_prefix('$') {
+            amount
+        } else if let Some(amount) = s.strip_suffix("USD") {
+            amount.trim_end()
+        } else {
+            s
+        };
+        let (dollars, cents) = match amount.split_once('.') {
+            Some((dollars, cents)) if (1..=2).contains(&cents.len()) => {
+                (dollars, cents)
+            },
+            Some(_) => return Err(ParseCurrencyError::Invalid),
+            None => (amount, "0")
+        };
+        let all_digits = |x: &str| x.bytes().all(|b| b.is_ascii_digit());
+        if dollars.is_empty() || !all_digits(dollars) || !all_digits(cents) {
+            return Err(ParseCurrencyError::Invalid);
+        }
+        // "5.1" is $5.10, not $5.01.
+        let scale = if cents.len() == 1 { 10 } else { 1 };
+        let cents = cents.parse::<IntegerType>()
+                         .map_err(|_| ParseCurrencyError::Invalid)? * scale;
+        dollars.parse::<IntegerType>().ok()
+               .and_then(|dollars| dollars.checked_mul(100))
+               .and_then(|total| total.checked_add(cents))
+               .map(Currency)
+               .ok_or(ParseCurrencyError::Overflow)
+    }
+}
+
+/// Why a string couldn't be read as a [`Currency`].
+///
+///   [`Currency`]: struct.Currency.html
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParseCurrencyError {
+    /// It wasn't written like `$5.10` or `5.10 USD`.
+    Invalid,
+    Overflow
+}
+
+impl fmt::Display for ParseCurrencyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseCurrencyError::Invalid => write!(
+                f, "expected an amount like $5.10 or 5.10 USD"
+            ),
+            ParseCurrencyError::Overflow => write!(
+                f, "that's too much money"
+            )
+        }
+    }
+}
+
+impl Error for ParseCurrencyError {}
+
+/// A [`Currency`] shown as a daily rate, like `$5.10/day`.
+///
+///   [`Currency`]: struct.Currency.html
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PerDay(pub Currency);
+
+impl Currency {
+    pub fn per_day(self) -> PerDay {
+        PerDay(self)
+    }
+}
+
+impl fmt::Display for PerDay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(&format!("{}/day", self.0))
+    }
+}
+
+/// Shown as the money it's worth, like `$5.10`. Fractions of a cent are
+/// left off.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&(*self / SECONDS_PER_DAY), f)
+    }
+}
+
+impl Token {
+    /// This many cents' worth of tokens, or as many tokens as there can be
+    /// if that's too many.
+    pub fn from_cents(cents: IntegerType) -> Token {
+        Token(cents.saturating_mul(SECONDS_PER_DAY.0))
+    }
+    /// How many whole cents these tokens are worth.
+    pub fn whole_cents(self) -> IntegerType {
+        self.0 / SECONDS_PER_DAY.0
     }
 }
 
+/// An amount of money per length of time, the way people think about
+/// bids: "$5 a day", "$30 a week".
+///
+/// The auction charges by the second, in [`Currency`], and [`per_second`]
+/// converts to that, rounding down, so nobody's ever charged more than the
+/// rate they asked for. (Daily rates convert exactly, since a cent a day
+/// is a token a second.)
+///
+///   [`Currency`]: struct.Currency.html
+///   [`per_second`]: #method.per_second
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "RawRate"))]
+pub struct Rate {
+    amount: Token,
+    per: Second
+}
+
+/// A [`Rate`] as it's serialised, before it's been checked.
+///
+///   [`Rate`]: struct.Rate.html
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct RawRate {
+    amount: Token,
+    per: Second
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawRate> for Rate {
+    type Error = &'static str;
+    fn try_from(raw: RawRate) -> Result<Rate, &'static str> {
+        Rate::new(raw.amount, raw.per).ok_or("a rate can't be per 0 seconds")
+    }
+}
+
+impl Rate {
+    /// `amount` every `per`, unless `per` is zero.
+    pub fn new(amount: Token, per: Second) -> Option<Rate> {
+        if per == Second(0) {
+            None
+        } else {
+            Some(Rate { amount, per })
+        }
+    }
+    pub fn hourly(amount: Token) -> Rate {
+        Rate { amount, per: SECONDS_PER_DAY / 24 }
+    }
+    pub fn daily(amount: Token) -> Rate {
+        Rate { amount, per: SECONDS_PER_DAY }
+    }
+    pub fn weekly(amount: Token) -> Rate {
+        Rate { amount, per: 7 * SECONDS_PER_DAY }
+    }
+
+    pub fn amount(self) -> Token {
+        self.amount
+    }
+    pub fn per(self) -> Second {
+        self.per
+    }
+
+    /// What to charge every second, rounded down.
+    pub fn per_second(self) -> Currency {
+        self.amount / self.per
+    }
+    /// Whether [`per_second`] is exactly this rate, with nothing rounded
+    /// off.
+    ///
+    ///   [`per_second`]: #method.per_second
+    pub fn is_exact(self) -> bool {
+        self.amount % self.per == Currency(0)
+    }
+}
+
+impl From<Currency> for Rate {
+    fn from(per_second: Currency) -> Rate {
+        Rate { amount: Token(per_second.0), per: Second(1) }
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let shown = match self.per {
+            Second(1) => self.per_second().per_day().to_string(),
+            Second(3_600) => format!("{}/hour", self.amount),
+            Second(86_400) => format!("{}/day", self.amount),
+            Second(604_800) => format!("{}/week", self.amount),
+            Second(per) => format!("{} every {} seconds", self.amount, per)
+        };
+        f.pad(&shown)
+    }
+}
+
+/// Multiplication that gives `None` instead of overflowing.
+pub trait CheckedMul<Rhs = Self> {
+    type Output;
+    fn checked_mul(self, rhs: Rhs) -> Option<Self::Output>;
+}
+/// Multiplication that stops at the largest value instead of overflowing.
+pub trait SaturatingMul<Rhs = Self> {
+    type Output;
+    fn saturating_mul(self, rhs: Rhs) -> Self::Output;
+}
+/// Division that gives `None` instead of dividing by zero.
+pub trait CheckedDiv<Rhs = Self> {
+    type Output;
+    fn checked_div(self, rhs: Rhs) -> Option<Self::Output>;
+}
+
+macro_rules! add_sub_ops {
+    ($($T:ident),*) => {$(
+        impl $T {
+            pub fn checked_add(self, rhs: $T) -> Option<$T> {
+                self.0.checked_add(rhs.0).map($T)
+            }
+            pub fn checked_sub(self, rhs: $T) -> Option<$T> {
+                self.0.checked_sub(rhs.0).map($T)
+            }
+            pub fn saturating_add(self, rhs: $T) -> $T {
+                $T(self.0.saturating_add(rhs.0))
+            }
+            pub fn saturating_sub(self, rhs: $T) -> $T {
+                $T(self.0.saturating_sub(rhs.0))
+            }
+        }
+    )*}
+}
+add_sub_ops!(Second, Currency, Token);
+
+/// Get at the number inside, so the macros below work for all of them.
+trait Raw {
+    fn raw(self) -> IntegerType;
+}
+impl Raw for IntegerType {
+    fn raw(self) -> IntegerType {
+        self
+    }
+}
+impl Raw for Second {
+    fn raw(self) -> IntegerType {
+        self.0
+    }
+}
+impl Raw for Currency {
+    fn raw(self) -> IntegerType {
+        self.0
+    }
+}
+impl Raw for Token {
+    fn raw(self) -> IntegerType {
+        self.0
+    }
+}
+
+macro_rules! mul_ops {
+    ($($Lhs:ty, $Rhs:ty => $Out:ident;)*) => {$(
+        impl CheckedMul<$Rhs> for $Lhs {
+            type Output = $Out;
+            fn checked_mul(self, rhs: $Rhs) -> Option<$Out> {
+                self.raw().checked_mul(rhs.raw()).map($Out)
+            }
+        }
+        impl SaturatingMul<$Rhs> for $Lhs {
+            type Output = $Out;
+            fn saturating_mul(self, rhs: $Rhs) -> $Out {
+                $Out(self.raw().saturating_mul(rhs.raw()))
+            }
+        }
+    )*}
+}
+mul_ops! {
+    Second, Currency => Token;
+    Currency, Second => Token;
+    Second, IntegerType => Second;
+    IntegerType, Second => Second;
+    Currency, IntegerType => Currency;
+    IntegerType, Currency => Currency;
+    Token, IntegerType => Token;
+    IntegerType, Token => Token;
+}
+
+macro_rules! div_ops {
+    ($($Lhs:ty, $Rhs:ty => $Out:ident;)*) => {$(
+        impl CheckedDiv<$Rhs> for $Lhs {
+            type Output = $Out;
+            fn checked_div(self, rhs: $Rhs) -> Option<$Out> {
+                self.raw().checked_div(rhs.raw()).map($Out)
+            }
+        }
+    )*}
+}
+div_ops! {
+    Token, Second => Currency;
+    Token, Currency => Second;
+    Second, IntegerType => Second;
+    Currency, IntegerType => Currency;
+    Token, IntegerType => Token;
+}
+
 impl Mul<Currency> for Second {
     type Output = Token;
     fn mul(self: Second, rhs: Currency) -> Token {
@@ -178,6 +561,112 @@ impl Rem<IntegerType> for Token {
     }
 }
 
+/// Why a value couldn't be converted to or from a rustorm `Value`.
+#[cfg(feature = "rustorm")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConvertValueError {
+    /// SQL integers are signed, so numbers from 2^63 up won't fit, and
+    /// negative ones don't mean anything.
+    OutOfRange,
+    /// The value wasn't an integer.
+    NotAnInteger
+}
+
+#[cfg(feature = "rustorm")]
+impl fmt::Display for ConvertValueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConvertValueError::OutOfRange => write!(
+                f, "the number is out of the range the database can store"
+            ),
+            ConvertValueError::NotAnInteger => write!(
+                f, "the database value isn't an integer"
+            )
+        }
+    }
+}
+
+#[cfg(feature = "rustorm")]
+impl Error for ConvertValueError {}
+
+/// A [`Second`], [`Currency`], [`Token`] or [`Timestamp`] (or a plain
+/// integer) that's been checked to fit in an SQL integer, so it can be
+/// handed to rustorm as a parameter. rustorm's `ToValue` can't fail, so
+/// this is where the check happens: `&SqlInteger::new(spent)?`.
+///
+/// Going the other way, rustorm reads values with `TryFrom<&Value>`, which
+/// this implements with the same check. So do the unit types, through it,
+/// so rustorm can read them directly.
+///
+///   [`Second`]: struct.Second.html
+///   [`Currency`]: struct.Currency.html
+///   [`Token`]: struct.Token.html
+///   [`Timestamp`]: struct.Timestamp.html
+#[cfg(feature = "rustorm")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SqlInteger<T>(T);
+
+#[cfg(feature = "rustorm")]
+impl<T: Copy + Into<IntegerType>> SqlInteger<T> {
+    /// `x`, unless it's 2^63 or more.
+    pub fn new(x: T) -> Result<SqlInteger<T>, ConvertValueError> {
+        if x.into() > i64::MAX as IntegerType {
+            Err(ConvertValueError::OutOfRange)
+        } else {
+            Ok(SqlInteger(x))
+        }
+    }
+
+    pub fn get(self) -> T {
+        self.0
+    }
+}
+
+#[cfg(feature = "rustorm")]
+impl<T: Copy + Into<IntegerType>> ToValue for SqlInteger<T> {
+    fn to_value(&self) -> Value {
+        // `new` made sure this fits.
+        Value::Bigint(self.0.into() as i64)
+    }
+}
+
+#[cfg(feature = "rustorm")]
+impl<T: From<IntegerType>> TryFrom<&Value> for SqlInteger<T> {
+    type Error = ConvertValueError;
+    fn try_from(value: &Value) -> Result<SqlInteger<T>, ConvertValueError> {
+        let x = match *value {
+            Value::Tinyint(x) => i64::from(x),
+            Value::Smallint(x) => i64::from(x),
+            Value::Int(x) => i64::from(x),
+            Value::Bigint(x) => x,
+            _ => return Err(ConvertValueError::NotAnInteger)
+        };
+        IntegerType::try_from(x)
+            .map(|x| SqlInteger(T::from(x)))
+            .map_err(|_| ConvertValueError::OutOfRange)
+    }
+}
+
+#[cfg(feature = "rustorm")]
+macro_rules! value_conversions {
+    ($($T:ident),*) => {$(
+        impl TryFrom<$T> for Value {
+            type Error = ConvertValueError;
+            fn try_from(x: $T) -> Result<Value, ConvertValueError> {
+                SqlInteger::new(x).map(|x| x.to_value())
+            }
+        }
+        impl TryFrom<&Value> for $T {
+            type Error = ConvertValueError;
+            fn try_from(value: &Value) -> Result<$T, ConvertValueError> {
+                SqlInteger::try_from(value).map(SqlInteger::get)
+            }
+        }
+    )*}
+}
+#[cfg(feature = "rustorm")]
+value_conversions!(Second, Currency, Token, Timestamp);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -238,7 +727,7 @@ mod tests {
 
     #[test]
     fn test_add_sub() {
-        for x in sparse_to_64!().take_while(|x| *x < (u64::max_value() / 2)) {
+        for x in sparse_to_64!().take_while(|x| *x < (u64::MAX / 2)) {
             for y in sparse_to_64!().take_while(|y| *y <= x) {
                 assert_eq!(
                     Second(x + y),
@@ -313,6 +802,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::op_ref)]
     fn test_mul() {
         // you know what, just test everything at once
         for x in sparse_to_32!() {
@@ -344,6 +834,7 @@ mod tests {
         }
     }
     #[test]
+    #[allow(clippy::op_ref)]
     fn test_div_rem() {
         for x in sparse_to_64!() {
             for y in sparse_to_32!().skip(1) {
@@ -396,4 +887,273 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_checked_saturating() {
+        for x in sparse_to_64!() {
+            for y in sparse_to_64!() {
+                assert_eq!(
+                    Second::from(x).checked_add(Second::from(y)),
+                    x.checked_add(y).map(Second),
+                    "Second::from({}).checked_add(Second::from({}))", x, y
+                );
+                assert_eq!(
+                    Currency::from(x).checked_sub(Currency::from(y)),
+                    x.checked_sub(y).map(Currency),
+                    "Currency::from({}).checked_sub(Currency::from({}))", x, y
+                );
+                assert_eq!(
+                    Token::from(x).saturating_add(Token::from(y)),
+                    Token(x.saturating_add(y)),
+                    "Token::from({}).saturating_add(Token::from({}))", x, y
+                );
+                assert_eq!(
+                    Token::from(x).saturating_sub(Token::from(y)),
+                    Token(x.saturating_sub(y)),
+                    "Token::from({}).saturating_sub(Token::from({}))", x, y
+                );
+                assert_eq!(
+                    Currency::from(x).checked_mul(Second::from(y)),
+                    x.checked_mul(y).map(Token),
+                    "Currency::from({}).checked_mul(Second::from({}))", x, y
+                );
+                assert_eq!(
+                    Second::from(x).saturating_mul(Currency::from(y)),
+                    Token(x.saturating_mul(y)),
+                    "Second::from({}).saturating_mul(Currency::from({}))",
+                    x, y
+                );
+                assert_eq!(
+                    Token::from(x).checked_div(Currency::from(y)),
+                    x.checked_div(y).map(Second),
+                    "Token::from({}).checked_div(Currency::from({}))", x, y
+                );
+                assert_eq!(
+                    Token::from(x).checked_div(Second::from(y)),
+                    x.checked_div(y).map(Currency),
+                    "Token::from({}).checked_div(Second::from({}))", x, y
+                );
+            }
+        }
+        assert_eq!(
+            Token::max_value().checked_add(Token::from(1)), None
+        );
+        assert_eq!(
+            Second::from(0).saturating_sub(Second::from(1)), Second::from(0)
+        );
+        assert_eq!(
+            Currency::max_value().saturating_mul(2), Currency::max_value()
+        );
+        assert_eq!(2.checked_mul(Token::max_value()), None);
+        assert_eq!(Second::from(7).checked_div(0), None);
+    }
+
+    #[test]
+    fn test_timestamp() {
+        for x in sparse_to_64!().take_while(|x| *x < (u64::MAX / 2)) {
+            for y in sparse_to_64!().take_while(|y| *y <= x) {
+                assert_eq!(
+                    Timestamp(x + y),
+                    Timestamp::from(x) + Second::from(y),
+                    "Timestamp::from({}) + Second::from({})", x, y
+                );
+                assert_eq!(
+                    Timestamp(x - y),
+                    Timestamp::from(x) - Second::from(y),
+                    "Timestamp::from({}) - Second::from({})", x, y
+                );
+                assert_eq!(
+                    Second(x - y),
+                    Timestamp::from(x) - Timestamp::from(y),
+                    "Timestamp::from({}) - Timestamp::from({})", x, y
+                );
+                let mut z = Timestamp::from(x);
+                z += Second::from(y);
+                assert_eq!(
+                    Timestamp(x + y), z,
+                    "Timestamp::from({}) += Second::from({})", x, y
+                );
+                let mut z = Timestamp::from(x);
+                z -= Second::from(y);
+                assert_eq!(
+                    Timestamp(x - y), z,
+                    "Timestamp::from({}) -= Second::from({})", x, y
+                );
+                assert_eq!(
+                    Timestamp::from(y).checked_duration_since(x.into()),
+                    y.checked_sub(x).map(Second),
+                    "Timestamp::from({}).checked_duration_since({})", y, x
+                );
+            }
+        }
+        assert_eq!(EPOCH + SECONDS_PER_DAY - EPOCH, SECONDS_PER_DAY);
+        assert_eq!((EPOCH + SECONDS_PER_DAY).since_epoch(), SECONDS_PER_DAY);
+        assert_eq!(Timestamp::max_value().checked_add(Second(1)), None);
+        assert_eq!(
+            Timestamp::max_value().saturating_add(Second(1)),
+            Timestamp::max_value()
+        );
+        assert_eq!(EPOCH.checked_sub(Second(1)), None);
+        assert_eq!(EPOCH.saturating_sub(Second(1)), EPOCH);
+    }
+
+    #[test]
+    fn test_currency_display_parse() {
+        for x in sparse_to_64!() {
+            let shown = Currency(x).to_string();
+            assert_eq!(
+                shown.parse(), Ok(Currency(x)),
+                "Currency({}) shown as {}", x, shown
+            );
+            assert_eq!(
+                shown[1..].parse::<Currency>(), Ok(Currency(x)),
+                "Currency({}) shown as {}", x, shown
+            );
+            assert_eq!(
+                format!("{} USD", &shown[1..]).parse(), Ok(Currency(x)),
+                "Currency({}) shown as {}", x, shown
+            );
+        }
+        assert_eq!(Currency(5_10).to_string(), "$5.10");
+        assert_eq!(Currency(5).to_string(), "$0.05");
+        assert_eq!(Currency(5_10).per_day().to_string(), "$5.10/day");
+        assert_eq!(format!("{:>9}", Currency(5_10)), "    $5.10");
+
+        assert_eq!("5.1".parse(), Ok(Currency(5_10)));
+        assert_eq!("$5".parse(), Ok(Currency(5_00)));
+        assert_eq!(" 0.05 USD ".parse(), Ok(Currency(5)));
+        assert_eq!(
+            "184467440737095516.15".parse(), Ok(Currency::max_value())
+        );
+        for invalid in &[
+            "", "$", "USD", "$.50", "5.", "5.105", "$5.10 USD", "-5",
+            "5,10", "$ 5", "5.-1", "five"
+        ] {
+            assert_eq!(
+                invalid.parse::<Currency>(), Err(ParseCurrencyError::Invalid),
+                "{:?}", invalid
+            );
+        }
+        assert_eq!(
+            "184467440737095516.16".parse::<Currency>(),
+            Err(ParseCurrencyError::Overflow)
+        );
+        assert_eq!(
+            "99999999999999999999".parse::<Currency>(),
+            Err(ParseCurrencyError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_token_display() {
+        assert_eq!(Token(0).to_string(), "$0.00");
+        assert_eq!((Currency(5_10) * SECONDS_PER_DAY).to_string(), "$5.10");
+        assert_eq!((Currency(5_10) * (SECONDS_PER_DAY / 24)).to_string(),
+                   "$0.21");  // 21.25¢
+        assert_eq!(Token(86_399).to_string(), "$0.00");
+        assert_eq!(Token(86_400).to_string(), "$0.01");
+    }
+
+    #[test]
+    fn test_rate() {
+        assert_eq!(Token::from_cents(5_10), Currency(5_10) * SECONDS_PER_DAY);
+        assert_eq!(Token::from_cents(5_10).whole_cents(), 5_10);
+        assert_eq!(Token::from_cents(u64::MAX), Token::max_value());
+        assert_eq!(Token(86_399).whole_cents(), 0);
+
+        let daily = Rate::daily(Token::from_cents(5_00));
+        assert_eq!(daily.per_second(), Currency(5_00));
+        assert!(daily.is_exact());
+        assert_eq!(daily.to_string(), "$5.00/day");
+
+        // $30 a week is $4.28 and 4/7 of a cent a day.
+        let weekly = Rate::weekly(Token::from_cents(30_00));
+        assert_eq!(weekly.per_second(), Currency(4_28));
+        assert!(!weekly.is_exact());
+        assert!(weekly.per_second() * weekly.per() <= weekly.amount());
+        assert_eq!(weekly.to_string(), "$30.00/week");
+
+        let hourly = Rate::hourly(Token::from_cents(25));
+        assert_eq!(hourly.per_second(), Currency(6_00));
+        assert!(hourly.is_exact());
+        assert_eq!(hourly.to_string(), "$0.25/hour");
+
+        assert_eq!(Rate::new(Token::from_cents(1), Second(0)), None);
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&weekly).unwrap();
+            assert_eq!(serde_json::from_str::<Rate>(&json).unwrap(), weekly);
+            assert!(serde_json::from_str::<Rate>(
+                r#"{"amount": 100, "per": 0}"#
+            ).is_err());
+        }
+        let odd = Rate::new(Token::from_cents(1), Second(7)).unwrap();
+        assert_eq!(odd.per_second(), Currency(12_342));
+        assert_eq!(odd.to_string(), "$0.01 every 7 seconds");
+
+        let charged = Rate::from(Currency(5_10));
+        assert_eq!(charged.per_second(), Currency(5_10));
+        assert_eq!(charged.to_string(), "$5.10/day");
+
+        for x in sparse_to_64!() {
+            for per in sparse_to_32!().skip(1) {
+                let rate = Rate::new(Token(x), Second(per)).unwrap();
+                let charged = rate.per_second() * rate.per();
+                assert!(
+                    charged <= rate.amount()
+                        && rate.amount() - charged < Token(per),
+                    "{:?} charges {:?}", rate, charged
+                );
+                assert_eq!(rate.is_exact(), charged == rate.amount());
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "rustorm")]
+    fn test_value_conversions() {
+        for x in sparse_to_64!() {
+            let value = Value::try_from(Currency(x));
+            if x > i64::MAX as u64 {
+                assert_eq!(value.unwrap_err(), ConvertValueError::OutOfRange);
+                continue;
+            }
+            let value = value.unwrap();
+            assert_eq!(value, Value::Bigint(x as i64));
+            assert_eq!(Currency::try_from(&value), Ok(Currency(x)));
+            assert_eq!(Second::try_from(&value), Ok(Second(x)));
+            assert_eq!(Token::try_from(&value), Ok(Token(x)));
+            assert_eq!(Timestamp::try_from(&value), Ok(Timestamp(x)));
+        }
+        assert_eq!(Value::try_from(Token(1 << 63)),
+                   Err(ConvertValueError::OutOfRange));
+        assert_eq!(Value::try_from(Second(i64::MAX as u64)),
+                   Ok(Value::Bigint(i64::MAX)));
+        assert_eq!(Timestamp::try_from(&Value::Int(5)), Ok(Timestamp(5)));
+        assert_eq!(Token::try_from(&Value::Bigint(-1)),
+                   Err(ConvertValueError::OutOfRange));
+        assert_eq!(Currency::try_from(&Value::Text("5".into())),
+                   Err(ConvertValueError::NotAnInteger));
+        assert_eq!(Second::try_from(&Value::Nil),
+                   Err(ConvertValueError::NotAnInteger));
+
+        // Straight to and from rustorm
+        let spent = SqlInteger::new(Token(5_00)).unwrap();
+        let params: [&dyn ToValue; 1] = [&spent];
+        assert_eq!(params[0].to_value(), Value::Bigint(5_00));
+        assert_eq!(spent.get(), Token(5_00));
+        assert_eq!(SqlInteger::new(Timestamp::max_value()),
+                   Err(ConvertValueError::OutOfRange));
+        let mut dao = rustorm_dao::Dao::new();
+        dao.insert("spent", params[0].to_value());
+        assert_eq!(dao.get::<Token>("spent").ok(), Some(Token(5_00)));
+
+        // Plain integers get the same checks.
+        assert_eq!(SqlInteger::<u64>::try_from(&Value::Smallint(5)),
+                   Ok(SqlInteger(5)));
+        assert_eq!(SqlInteger::<u64>::try_from(&Value::Int(-5)),
+                   Err(ConvertValueError::OutOfRange));
+        assert_eq!(SqlInteger::new(u64::MAX),
+                   Err(ConvertValueError::OutOfRange));
+    }
 }
@@ -0,0 +1,204 @@
+//! Commands for looking after a database by hand.
+
+use crate::now;
+use infinite_auction::{validate_bid, Bid, BidId};
+use project_brilliant_db::{
+    describe_db, open_db, Account, Ad, AdBox, AdBoxId, Advert, AdvertiserId,
+    CreativeId, PublisherId
+};
+use project_brilliant_utilities::{Currency, Timestamp, Token, EPOCH};
+use project_brilliant_utilities::SECONDS_PER_DAY;
+use std::error::Error;
+
+type Outcome = Result<bool, Box<dyn Error>>;
+
+/// Create a database, or bring one up to date.
+pub fn init(uri: &str) -> Outcome {
+    open_db(uri)?;
+    let fingerprint = describe_db(uri)?.fingerprint();
+    println!("The database is up to date (fingerprint {}).", fingerprint);
+    Ok(true)
+}
+
+pub fn add_advertiser(uri: &str, name: &str) -> Outcome {
+    let AdvertiserId(id) = open_db(uri)?.insert_advertiser(name)?;
+    println!("Added advertiser {}.", id);
+    Ok(true)
+}
+
+pub fn add_publisher(uri: &str, name: &str) -> Outcome {
+    let PublisherId(id) = open_db(uri)?.insert_publisher(name)?;
+    println!("Added publisher {}.", id);
+    Ok(true)
+}
+
+/// `args` are the publisher, name, width, height, minimum bid, increment
+/// and fee percentage, then optionally the house ad's image and link.
+pub fn add_ad_box(uri: &str, args: &[&str]) -> Outcome {
+    let (house_image_url, house_link_url) = match args[7..] {
+        [] => (None, None),
+        [image, link] => (Some(url(image)?), Some(url(link)?)),
+        _ => return Err("a house ad needs an image and a link".into())
+    };
+    let ad_box = AdBox {
+        publisher: PublisherId(args[0].parse()?),
+        name: args[1].into(),
+        width: args[2].parse()?,
+        height: args[3].parse()?,
+        min_bid: args[4].parse()?,
+        increment: args[5].parse()?,
+        fee_percent: args[6].trim_end_matches('%').parse()?,
+        house_image_url,
+        house_link_url
+    };
+    let AdBoxId(id) = open_db(uri)?.insert_ad_box(&ad_box)?;
+    println!("Added ad box {}.", id);
+    Ok(true)
+}
+
+/// `args` are the advertiser, width, height, image, link and alt text.
+pub fn add_creative(uri: &str, args: &[&str]) -> Outcome {
+    let advertiser = AdvertiserId(args[0].parse()?);
+    let ad = Ad {
+        width: args[1].parse()?,
+        height: args[2].parse()?,
+        image_url: url(args[3])?,
+        link_url: url(args[4])?,
+        alt_text: args[5].into()
+    };
+    let CreativeId(id) = open_db(uri)?.insert_creative(advertiser, &ad)?;
+    println!("Added creative {}.", id);
+    Ok(true)
+}
+
+pub fn list(uri: &str, what: &str) -> Outcome {
+    let db = open_db(uri)?;
+    match what {
+        "advertisers" => for (AdvertiserId(id), name) in db.advertisers()? {
+            println!("{:>5}  {}", id, name);
+        },
+        "publishers" => for (PublisherId(id), name) in db.publishers()? {
+            println!("{:>5}  {}", id, name);
+        },
+        "ad-boxes" => for (AdBoxId(id), ad_box) in db.ad_boxes()? {
+            println!("{:>5}  {} (publisher {}), {}×{}, minimum bid {}, \
+                      increment {}, {}% fee",
+                     id, ad_box.name, ad_box.publisher.0, ad_box.width,
+                     ad_box.height, ad_box.min_bid.per_day(),
+                     ad_box.increment.per_day(), ad_box.fee_percent);
+            if let (Some(image), Some(link)) =
+                   (&ad_box.house_image_url, &ad_box.house_link_url) {
+                println!("       house ad {} linking to {}", image, link);
+            }
+        },
+        "creatives" => for (CreativeId(id), advertiser, ad)
+                           in db.creatives()? {
+            println!("{:>5}  advertiser {}, {}×{}, {} linking to {} ({:?})",
+                     id, advertiser.0, ad.width, ad.height, ad.image_url,
+                     ad.link_url, ad.alt_text);
+        },
+        _ => return Err(format!(
+            "can't list {}: try advertisers, publishers, ad-boxes or \
+             creatives", what
+        ).into())
+    }
+    Ok(true)
+}
+
+/// `args` are the ad box, advertiser, creative, bid (per day), expense
+/// limit and expiry (as a Unix time).
+pub fn place_bid(uri: &str, args: &[&str]) -> Outcome {
+    let ad_box = AdBoxId(args[0].parse()?);
+    let limit: Currency = args[4].parse()?;
+    let bid = Bid {
+        bid: args[3].parse()?,
+        expense_limit: u64::from(limit)
+            .checked_mul(u64::from(SECONDS_PER_DAY)).map(Token::from)
+            .ok_or("the expense limit is too large")?,
+        expiry: Timestamp::from(args[5].parse::<u64>()?),
+        data: Advert {
+            advertiser: AdvertiserId(args[1].parse()?),
+            creative: CreativeId(args[2].parse()?)
+        }
+    };
+    let db = open_db(uri)?;
+    let now = now();
+    validate_bid(&bid, &db.auction_params(ad_box)?, now)?;
+    db.check_advert(ad_box, bid.data)??;
+    // As with the API, what's been shown so far is charged first.
+    db.settle_ad_box(ad_box, now)?;
+    let BidId(id) = db.insert_bid(ad_box, &bid, now)?;
+    println!("Placed bid {}.", id);
+    Ok(true)
+}
+
+pub fn cancel_bid(uri: &str, id: &str) -> Outcome {
+    let id = BidId(id.parse()?);
+    let db = open_db(uri)?;
+    let now = now();
+    let stored = db.get_bid(id)?
+                   .ok_or_else(|| format!("no live bid has id {}", id.0))?;
+    db.settle_ad_box(stored.ad_box, now)?;
+    db.cancel_bid(id, now)?;
+    println!("Cancelled bid {}.", id.0);
+    Ok(true)
+}
+
+/// Settle an ad box's auction up to now, and print what it's going to
+/// show, if nobody changes their bids.
+pub fn run_auction(uri: &str, ad_box: &str) -> Outcome {
+    let ad_box = AdBoxId(ad_box.parse()?);
+    let db = open_db(uri)?;
+    let charged = db.settle_ad_box(ad_box, now())?;
+    let spent = charged.iter().fold(Token::from(0), |total, segment| {
+        total.saturating_add(segment.spent)
+    });
+    println!("Charged {} for {} segments since it was last settled.",
+             spent, charged.len());
+
+    let forecast = db.forecast(ad_box)?;
+    if forecast.is_empty() {
+        println!("Nothing is due to be shown.");
+    }
+    for segment in &forecast {
+        println!("{} until {}: bid {} at {}, spending {}",
+                 seconds(segment.start), seconds(segment.end),
+                 segment.winner.0, segment.rate.per_day(), segment.spent);
+    }
+    Ok(true)
+}
+
+/// Print what everyone's paid and been paid, and check the ledger adds
+/// up.
+pub fn balances(uri: &str) -> Outcome {
+    let db = open_db(uri)?;
+    for (id, name) in db.advertisers()? {
+        let balance = db.balance(Account::Advertiser(id))?;
+        println!("Advertiser {} ({}) has paid {}.",
+                 id.0, name, balance.debits);
+    }
+    for (id, name) in db.publishers()? {
+        let balance = db.balance(Account::Publisher(id))?;
+        println!("Publisher {} ({}) has earned {}.",
+                 id.0, name, balance.credits);
+    }
+    let balance = db.balance(Account::Platform)?;
+    println!("The platform has earned {}.", balance.credits);
+    db.check_ledger()?;
+    println!("The ledger adds up.");
+    Ok(true)
+}
+
+fn seconds(time: Timestamp) -> u64 {
+    u64::from(time - EPOCH)
+}
+
+/// Check a URL's one an ad can link to, and that can go in a header.
+fn url(url: &str) -> Result<String, Box<dyn Error>> {
+    let scheme = url.starts_with("https://") || url.starts_with("http://");
+    if scheme && url.bytes().all(|b| b.is_ascii_graphic()) {
+        Ok(url.into())
+    } else {
+        Err(format!("{} isn't an http or https URL", url).into())
+    }
+}
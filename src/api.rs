@@ -0,0 +1,695 @@
+//! The HTTP API: JSON endpoints for placing and managing bids, and the ads
+//! themselves.
+//!
+//! - `GET /box/<id>/bids` lists an ad box's bids.
+//! - `POST /box/<id>/bids` places a bid, given its `advertiser`,
+//!   `creative`, `bid`, `expense_limit` and `expiry`.
+//! - `GET /bid/<id>` shows one bid.
+//! - `PATCH /bid/<id>` changes a bid's `bid`, `expense_limit` or both.
+//! - `DELETE /bid/<id>` cancels a bid.
+//! - `GET /bid/<id>/segments` lists what a bid's spend has bought so far:
+//!   each stretch of time it was shown, what it `spent`, and how many
+//!   `impressions` and `clicks` it got.
+//! - `GET /box/<id>/serve` is an HTML fragment showing what the ad box is
+//!   showing right now: the winning bid's creative, or else the
+//!   publisher's house ad. If there's neither, it's empty (a 204). It's
+//!   meant to go in an iframe, since its link is relative.
+//! - `GET /click/<token>` is where a served ad links: it counts the click,
+//!   then redirects to the advertiser.
+//!
+//! Money is in the units of project-brilliant-utilities: `bid` and `price`
+//! are `Currency` (a cent a day), and `expense_limit` and `spent` are
+//! `Token`s. Times are seconds since the Unix epoch.
+//!
+//! Each bid comes back with a projection of the auction as it stands: the
+//! bid's `position` (1 is being shown), and when it'll next be shown, and
+//! at what `price`, if nobody changes their bids. Before anything's
+//! changed, the ad box is settled up to the present, so what's been shown
+//! so far is charged on the old terms.
+
+use infinite_auction::{validate_bid, Amendment, Bid, BidId};
+use project_brilliant_db::{
+    Ad, AdBoxId, Advert, AdvertiserId, CreativeId, DB, Error, StoredBid
+};
+use project_brilliant_utilities::{Currency, Timestamp, Token};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{fmt, io};
+use tiny_http::{Header, Method, Request};
+
+/// What to send back.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Vec<u8>
+}
+
+impl Response {
+    fn json(status: u16, value: &impl Serialize) -> Response {
+        Response {
+            status,
+            headers: vec![("Content-Type", "application/json".into())],
+            body: serde_json::to_vec(value)
+                      .expect("API responses always serialise")
+        }
+    }
+
+    fn error(status: u16, message: impl fmt::Display) -> Response {
+        #[derive(Serialize)]
+        struct ErrorBody {
+            error: String
+        }
+        Response::json(status, &ErrorBody { error: message.to_string() })
+    }
+
+    fn html(status: u16, html: String) -> Response {
+        Response {
+            status,
+            headers: vec![
+                ("Content-Type", "text/html; charset=utf-8".into()),
+                // It's only right until the auction moves on.
+                ("Cache-Control", "no-store".into())
+            ],
+            body: html.into_bytes()
+        }
+    }
+
+    fn empty(status: u16) -> Response {
+        Response { status, headers: Vec::new(), body: Vec::new() }
+    }
+}
+
+impl From<Error> for Response {
+    fn from(error: Error) -> Response {
+        let status = match error {
+            Error::UnknownAdBox(_) | Error::UnknownBid(_) => 404,
+            Error::ConstraintViolation(_) | Error::Conversion(_) => 422,
+            _ => 500
+        };
+        Response::error(status, error)
+    }
+}
+
+/// A request's outcome. Both are sent back; it's only an `Err` so that `?`
+/// works.
+type Outcome = Result<Response, Response>;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NewBid {
+    advertiser: u64,
+    creative: u64,
+    bid: Currency,
+    expense_limit: Token,
+    expiry: Timestamp
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Changes {
+    bid: Option<Currency>,
+    expense_limit: Option<Token>
+}
+
+#[derive(Serialize)]
+struct BidView {
+    id: u64,
+    ad_box: u64,
+    advertiser: u64,
+    creative: u64,
+    bid: Currency,
+    /// As it was set, counting what's been spent.
+    expense_limit: Token,
+    spent: Token,
+    expiry: Timestamp,
+    placed_at: Timestamp
+}
+
+#[derive(Serialize)]
+struct Projection {
+    /// `None` if the bid won't be shown again, as things stand: it's run
+    /// out or expired, or will have by the time the bids ahead of it have.
+    position: Option<usize>,
+    shown_from: Option<Timestamp>,
+    price: Option<Currency>
+}
+
+#[derive(Serialize)]
+struct SegmentView {
+    start: Timestamp,
+    end: Timestamp,
+    spent: Token,
+    impressions: u64,
+    clicks: u64
+}
+
+#[derive(Serialize)]
+struct BidStatus {
+    bid: BidView,
+    projection: Projection
+}
+
+/// Read and answer one request.
+pub fn respond(db: &DB, mut request: Request, now: Timestamp)
+    -> io::Result<()>
+{
+    let mut body = Vec::new();
+    let response = match request.as_reader().read_to_end(&mut body) {
+        Ok(_) => handle(db, request.method(), request.url(), &body, now),
+        Err(e) => Response::error(400, e)
+    };
+    let mut http = tiny_http::Response::from_data(response.body)
+                       .with_status_code(response.status);
+    for (name, value) in response.headers {
+        http = http.with_header(
+            Header::from_bytes(name, value).expect("headers are ASCII")
+        );
+    }
+    request.respond(http)
+}
+
+/// Answer a request, as of `now`.
+pub fn handle(
+    db: &DB, method: &Method, url: &str, body: &[u8], now: Timestamp
+) -> Response {
+    let path = url.split('?').next().unwrap_or_default();
+    let parts: Vec<&str> = path.split('/').skip(1).collect();
+    let outcome = match (method, parts.as_slice()) {
+        (Method::Get, ["box", id, "bids"]) => {
+            parse_id(id).and_then(|id| list_bids(db, AdBoxId(id), now))
+        },
+        (Method::Post, ["box", id, "bids"]) => {
+            parse_id(id).and_then(|id| place_bid(db, AdBoxId(id), body, now))
+        },
+        (Method::Get, ["bid", id]) => {
+            parse_id(id).and_then(|id| get_bid(db, BidId(id), now))
+        },
+        (Method::Patch, ["bid", id]) => {
+            parse_id(id).and_then(|id| amend_bid(db, BidId(id), body, now))
+        },
+        (Method::Delete, ["bid", id]) => {
+            parse_id(id).and_then(|id| cancel_bid(db, BidId(id), now))
+        },
+        (Method::Get, ["bid", id, "segments"]) => {
+            parse_id(id).and_then(|id| segments(db, BidId(id)))
+        },
+        (Method::Get, ["box", id, "serve"]) => {
+            parse_id(id).and_then(|id| serve_ad(db, AdBoxId(id), now))
+        },
+        (Method::Get, ["click", token]) => click(db, token, now),
+        (_, ["box", _, "bids"]) | (_, ["box", _, "serve"])
+        | (_, ["bid", _]) | (_, ["bid", _, "segments"])
+        | (_, ["click", _]) => {
+            Err(Response::error(405, "method not allowed"))
+        },
+        _ => Err(Response::error(404, "not found"))
+    };
+    outcome.unwrap_or_else(|response| response)
+}
+
+fn parse_id(id: &str) -> Result<u64, Response> {
+    id.parse().map_err(|_| Response::error(404, "not found"))
+}
+
+fn parse<T: DeserializeOwned>(body: &[u8]) -> Result<T, Response> {
+    serde_json::from_slice(body).map_err(|e| Response::error(400, e))
+}
+
+fn list_bids(db: &DB, ad_box: AdBoxId, now: Timestamp) -> Outcome {
+    db.settle_ad_box(ad_box, now)?;
+    Ok(Response::json(200, &statuses(db, ad_box)?))
+}
+
+fn place_bid(db: &DB, ad_box: AdBoxId, body: &[u8], now: Timestamp)
+    -> Outcome
+{
+    let new: NewBid = parse(body)?;
+    let bid = Bid {
+        bid: new.bid,
+        expense_limit: new.expense_limit,
+        expiry: new.expiry,
+        data: Advert {
+            advertiser: AdvertiserId(new.advertiser),
+            creative: CreativeId(new.creative)
+        }
+    };
+    validate_bid(&bid, &db.auction_params(ad_box)?, now)
+        .map_err(|rejection| Response::error(422, rejection))?;
+    db.check_advert(ad_box, bid.data)?
+        .map_err(|rejection| Response::error(422, rejection))?;
+    db.settle_ad_box(ad_box, now)?;
+    let id = db.insert_bid(ad_box, &bid, now)?;
+    Ok(Response::json(201, &status(db, ad_box, id)?))
+}
+
+fn get_bid(db: &DB, id: BidId, now: Timestamp) -> Outcome {
+    let ad_box = live_bid(db, id)?.ad_box;
+    db.settle_ad_box(ad_box, now)?;
+    Ok(Response::json(200, &status(db, ad_box, id)?))
+}
+
+fn amend_bid(db: &DB, id: BidId, body: &[u8], now: Timestamp) -> Outcome {
+    let changes: Changes = parse(body)?;
+    if changes.bid.is_none() && changes.expense_limit.is_none() {
+        return Err(Response::error(400, "there's nothing to change"));
+    }
+    // The new terms are checked against what the bid has left once
+    // what's been shown so far is charged. All of it happens together, so
+    // the bid isn't left half changed, or changed by someone else in
+    // between.
+    let status = db.transaction(|| {
+        db.settle_ad_box(live_bid(db, id)?.ad_box, now)?;
+        let stored = live_bid(db, id)?;
+        let mut amended = stored;
+        if let Some(amount) = changes.bid {
+            amended.bid.bid = amount;
+        }
+        if let Some(limit) = changes.expense_limit {
+            amended.bid.expense_limit = limit;
+        }
+        let bid = Bid { expense_limit: amended.remaining(), ..amended.bid };
+        validate_bid(&bid, &db.auction_params(stored.ad_box)?, now)
+            .map_err(|rejection| Response::error(422, rejection))?;
+
+        if let Some(amount) = changes.bid {
+            db.amend_bid(id, Amendment::Bid(amount), now)?;
+        }
+        if let Some(limit) = changes.expense_limit {
+            db.amend_bid(id, Amendment::ExpenseLimit(limit), now)?;
+        }
+        status(db, stored.ad_box, id)
+    })?;
+    Ok(Response::json(200, &status))
+}
+
+fn cancel_bid(db: &DB, id: BidId, now: Timestamp) -> Outcome {
+    db.settle_ad_box(live_bid(db, id)?.ad_box, now)?;
+    db.cancel_bid(id, now)?;
+    Ok(Response::empty(204))
+}
+
+/// What a bid's bought, as of when its ad box was last settled.
+fn segments(db: &DB, id: BidId) -> Outcome {
+    let views: Vec<_> = db.segment_stats(id)?.into_iter().map(|stats| {
+        SegmentView {
+            start: stats.start,
+            end: stats.end,
+            spent: stats.spent,
+            impressions: stats.impressions,
+            clicks: stats.clicks
+        }
+    }).collect();
+    Ok(Response::json(200, &views))
+}
+
+fn serve_ad(db: &DB, ad_box: AdBoxId, now: Timestamp) -> Outcome {
+    db.settle_ad_box(ad_box, now)?;
+    let (bid, ad) = match db.current_winner(ad_box)? {
+        Some(id) => {
+            let creative = live_bid(db, id)?.bid.data.creative;
+            (Some(id), Some(db.creative(creative)?.ok_or_else(|| {
+                Response::error(500, format!("creative {} has gone",
+                                             creative.0))
+            })?))
+        },
+        None => (None, db.house_ad(ad_box)?)
+    };
+    let ad = match ad {
+        Some(ad) => ad,
+        None => return Ok(Response::empty(204))
+    };
+    let token = db.record_impression(ad_box, bid, &ad.link_url, now)?;
+    Ok(Response::html(200, ad_html(&ad, &token)))
+}
+
+fn ad_html(ad: &Ad, token: &str) -> String {
+    format!(
+        "<a href=\"/click/{}\" target=\"_top\"><img src=\"{}\" \
+         width=\"{}\" height=\"{}\" alt=\"{}\"></a>",
+        escape(token), escape(&ad.image_url), ad.width, ad.height,
+        escape(&ad.alt_text)
+    )
+}
+
+fn click(db: &DB, token: &str, now: Timestamp) -> Outcome {
+    let click = db.record_click(token, now)?
+                  .ok_or_else(|| Response::error(404, "not found"))?;
+    // It goes in a header, so it mustn't be able to start another.
+    if !click.link_url.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err(Response::error(500, "the ad's link isn't a valid URL"));
+    }
+    Ok(Response {
+        status: 302,
+        headers: vec![
+            ("Location", click.link_url),
+            ("Cache-Control", "no-store".into())
+        ],
+        body: Vec::new()
+    })
+}
+
+/// Make text safe to put in HTML, even in an attribute.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+fn live_bid(db: &DB, id: BidId) -> Result<StoredBid, Response> {
+    db.get_bid(id)?.ok_or_else(|| {
+        Response::error(404, format!("no live bid has id {}", id.0))
+    })
+}
+
+fn status(db: &DB, ad_box: AdBoxId, id: BidId) -> Result<BidStatus, Response> {
+    statuses(db, ad_box)?.into_iter()
+        .find(|status| status.bid.id == id.0)
+        .ok_or_else(|| Response::error(404, "the bid has gone"))
+}
+
+/// Every live bid on an ad box, and how it's doing. The ad box should have
+/// been settled up to the present.
+fn statuses(db: &DB, ad_box: AdBoxId) -> Result<Vec<BidStatus>, Response> {
+    let forecast = db.forecast(ad_box)?;
+    let bids = db.bids_for_ad_box(ad_box)?;
+    // The order bids will be shown in, starting with the current winner.
+    let mut ranking = Vec::new();
+    for segment in &forecast {
+        if !ranking.contains(&segment.winner) {
+            ranking.push(segment.winner);
+        }
+    }
+
+    Ok(bids.iter().map(|stored| {
+        let next = forecast.iter().find(|segment| segment.winner == stored.id);
+        BidStatus {
+            bid: BidView {
+                id: stored.id.0,
+                ad_box: stored.ad_box.0,
+                advertiser: stored.bid.data.advertiser.0,
+                creative: stored.bid.data.creative.0,
+                bid: stored.bid.bid,
+                expense_limit: stored.bid.expense_limit,
+                spent: stored.spent,
+                expiry: stored.bid.expiry,
+                placed_at: stored.placed_at
+            },
+            projection: Projection {
+                position: ranking.iter().position(|&id| id == stored.id)
+                                 .map(|index| index + 1),
+                shown_from: next.map(|segment| segment.start),
+                price: next.map(|segment| segment.rate)
+            }
+        }
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use project_brilliant_db::{testing::{at, seeded}, AdBox};
+    use project_brilliant_utilities::Currency;
+    use serde_json::{json, Value};
+
+    /// A database with two ad boxes, each with a minimum bid of $1/day and
+    /// an increment of 10¢/day, and one advertiser with one creative. Only
+    /// the first has a house ad.
+    fn fixture() -> DB {
+        let seeded = seeded();
+        let sidebar = AdBox {
+            min_bid: Currency::from(100),
+            increment: Currency::from(10),
+            house_image_url: Some("https://example.org/house.png".into()),
+            house_link_url: Some("https://example.org".into()),
+            ..seeded.ad_box()
+        };
+        seeded.add_ad_box(&sidebar);
+        seeded.add_ad_box(&AdBox {
+            name: "Banner".into(),
+            width: 468,
+            height: 60,
+            house_image_url: None,
+            house_link_url: None,
+            ..sidebar
+        });
+        seeded.db
+    }
+
+    fn request(db: &DB, method: Method, url: &str, body: Value,
+               now: u64) -> (u16, Value) {
+        let body = if body.is_null() {
+            Vec::new()
+        } else {
+            serde_json::to_vec(&body).unwrap()
+        };
+        let response = handle(db, &method, url, &body, at(now));
+        let body = if response.body.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(&response.body).unwrap()
+        };
+        (response.status, body)
+    }
+
+    fn new_bid(bid: u64, expense_limit: u64) -> Value {
+        json!({
+            "advertiser": 1,
+            "creative": 1,
+            "bid": bid,
+            "expense_limit": expense_limit,
+            "expiry": 1_000_000
+        })
+    }
+
+    #[test]
+    fn place_and_list_bids() {
+        let db = fixture();
+        let (status, body) = request(&db, Method::Post, "/box/1/bids",
+                                     new_bid(500, 1_000_000), 100);
+        assert_eq!(status, 201);
+        assert_eq!(body["bid"]["id"], 1);
+        assert_eq!(body["bid"]["placed_at"], 100);
+        assert_eq!(body["projection"], json!({
+            "position": 1, "shown_from": 100, "price": 100
+        }));
+
+        // It pays 10¢/day more than the first bid, so it runs out after
+        // 100,000 seconds.
+        let (status, body) = request(&db, Method::Post, "/box/1/bids",
+                                     new_bid(800, 51_000_000), 200);
+        assert_eq!(status, 201);
+        assert_eq!(body["projection"], json!({
+            "position": 1, "shown_from": 200, "price": 510
+        }));
+
+        let (status, body) = request(&db, Method::Get, "/box/1/bids",
+                                     Value::Null, 300);
+        assert_eq!(status, 200);
+        assert_eq!(body[0]["bid"]["spent"], 100 * 100);
+        assert_eq!(body[0]["bid"]["expense_limit"], 1_000_000);
+        assert_eq!(body[0]["projection"], json!({
+            "position": 2, "shown_from": 100_200, "price": 100
+        }));
+        assert_eq!(body[1]["bid"]["spent"], 100 * 510);
+        assert_eq!(body[1]["projection"]["position"], 1);
+    }
+
+    #[test]
+    fn positions_follow_the_forecast() {
+        let db = fixture();
+        request(&db, Method::Post, "/box/1/bids", new_bid(800, 1_000_000),
+                0);
+        // Outbid until after it expires: the first bid would run out at
+        // 1,960 seconds.
+        let mut bid = new_bid(500, 1_000_000);
+        bid["expiry"] = json!(1000);
+        let (_, body) = request(&db, Method::Post, "/box/1/bids", bid, 0);
+        assert_eq!(body["projection"], json!({
+            "position": null, "shown_from": null, "price": null
+        }));
+        // Behind it, but shown once the first bid runs out. That's later
+        // now, as the first bid pays less once the second expires.
+        let (_, body) = request(&db, Method::Post, "/box/1/bids",
+                                new_bid(300, 1_000_000), 0);
+        assert_eq!(body["projection"], json!({
+            "position": 2, "shown_from": 2580, "price": 100
+        }));
+    }
+
+    #[test]
+    fn reject_bids() {
+        let db = fixture();
+        let (status, body) = request(&db, Method::Post, "/box/1/bids",
+                                     new_bid(50, 1_000_000), 100);
+        assert_eq!(status, 422);
+        assert_eq!(body["error"], "the bid is below the ad box's minimum \
+                                   bid of $1.00/day");
+        let (status, _) = request(&db, Method::Post, "/box/3/bids",
+                                  new_bid(500, 1_000_000), 100);
+        assert_eq!(status, 404);
+        let (status, _) = request(&db, Method::Post, "/box/1/bids",
+                                  json!({"bid": 500}), 100);
+        assert_eq!(status, 400);
+        let mut unknown_creative = new_bid(500, 1_000_000);
+        unknown_creative["creative"] = json!(2);
+        let (status, body) = request(&db, Method::Post, "/box/1/bids",
+                                     unknown_creative, 100);
+        assert_eq!(status, 422);
+        assert_eq!(body["error"], "no creative has id 2");
+        db.insert_advertiser("Other").unwrap();
+        let mut someone_elses = new_bid(500, 1_000_000);
+        someone_elses["advertiser"] = json!(2);
+        let (status, body) = request(&db, Method::Post, "/box/1/bids",
+                                     someone_elses, 100);
+        assert_eq!(status, 422);
+        assert_eq!(body["error"], "the creative belongs to another \
+                                   advertiser");
+        let (status, body) = request(&db, Method::Post, "/box/2/bids",
+                                     new_bid(500, 1_000_000), 100);
+        assert_eq!(status, 422);
+        assert_eq!(body["error"], "the creative is 120x600, but the ad box \
+                                   is 468x60");
+        let (status, body) = request(&db, Method::Get, "/box/1/bids",
+                                     Value::Null, 100);
+        assert_eq!((status, body), (200, json!([])));
+    }
+
+    #[test]
+    fn amend_and_cancel_bids() {
+        let db = fixture();
+        request(&db, Method::Post, "/box/1/bids", new_bid(500, 1_000_000),
+                0);
+        request(&db, Method::Post, "/box/1/bids", new_bid(800, 1_000_000),
+                0);
+
+        let (status, body) = request(&db, Method::Patch, "/bid/1",
+                                     json!({"bid": 900}), 100);
+        assert_eq!(status, 200);
+        assert_eq!(body["bid"]["bid"], 900);
+        assert_eq!(body["projection"], json!({
+            "position": 1, "shown_from": 100, "price": 810
+        }));
+        // The first 100 seconds were charged before the amendment.
+        let (_, body) = request(&db, Method::Get, "/bid/2", Value::Null,
+                                100);
+        assert_eq!(body["bid"]["spent"], 100 * 510);
+
+        let (status, body) = request(&db, Method::Patch, "/bid/1",
+                                     json!({"expense_limit": 1}), 100);
+        assert_eq!(status, 422);
+        assert_eq!(body["error"], "the expense limit won't pay for a second \
+                                   of display");
+        let (status, _) = request(&db, Method::Patch, "/bid/1", json!({}),
+                                  100);
+        assert_eq!(status, 400);
+
+        let (status, body) = request(&db, Method::Delete, "/bid/1",
+                                     Value::Null, 200);
+        assert_eq!((status, body), (204, Value::Null));
+        let (status, _) = request(&db, Method::Get, "/bid/1", Value::Null,
+                                  200);
+        assert_eq!(status, 404);
+        let (status, _) = request(&db, Method::Delete, "/bid/1",
+                                  Value::Null, 200);
+        assert_eq!(status, 404);
+        let (_, body) = request(&db, Method::Get, "/bid/2", Value::Null,
+                                200);
+        assert_eq!(body["projection"]["position"], 1);
+    }
+
+    #[test]
+    fn serve_ads() {
+        let db = fixture();
+        // The status, and the HTML with its click token taken out
+        let serve = |url, now| {
+            let response = handle(&db, &Method::Get, url, &[], at(now));
+            let html = String::from_utf8(response.body).unwrap();
+            let html = match html.split(&['/', '"'][..]).nth(3) {
+                Some(token) => html.replacen(token, "token", 1),
+                None => html
+            };
+            (response.status, html)
+        };
+        let house = "<a href=\"/click/token\" target=\"_top\">\
+                     <img src=\"https://example.org/house.png\" \
+                     width=\"120\" height=\"600\" alt=\"\"></a>";
+        assert_eq!(serve("/box/1/serve", 0), (200, house.into()));
+        assert_eq!(serve("/box/2/serve", 0), (204, String::new()));
+        assert_eq!(serve("/box/3/serve", 0).0, 404);
+
+        let mut bid = new_bid(500, 1_000_000);
+        bid["expiry"] = json!(200);
+        request(&db, Method::Post, "/box/1/bids", bid, 100);
+        assert_eq!(serve("/box/1/serve", 150), (200, "\
+            <a href=\"/click/token\" target=\"_top\">\
+            <img src=\"https://example.com/ad.png\" width=\"120\" \
+            height=\"600\" alt=\"An ad\"></a>".into()));
+        // Expired
+        assert_eq!(serve("/box/1/serve", 200), (200, house.into()));
+    }
+
+    #[test]
+    fn clicks() {
+        let db = fixture();
+        let mut bid = new_bid(500, 1_000_000);
+        bid["expiry"] = json!(200);
+        request(&db, Method::Post, "/box/1/bids", bid, 100);
+        let html = handle(&db, &Method::Get, "/box/1/serve", &[], at(150))
+                       .body;
+        let html = String::from_utf8(html).unwrap();
+        let link = html.split('"').nth(1).unwrap();
+
+        let response = handle(&db, &Method::Get, link, &[], at(160));
+        assert_eq!(response.status, 302);
+        assert_eq!(response.headers[0],
+                   ("Location", "https://example.com".into()));
+        let (status, _) = request(&db, Method::Get, "/click/nonsense",
+                                  Value::Null, 160);
+        assert_eq!(status, 404);
+
+        // Settled past the impression
+        request(&db, Method::Get, "/box/1/bids", Value::Null, 250);
+        let (status, body) = request(&db, Method::Get, "/bid/1/segments",
+                                     Value::Null, 250);
+        assert_eq!(status, 200);
+        assert_eq!(body, json!([{
+            "start": 100,
+            "end": 200,
+            "spent": 100 * 100,
+            "impressions": 1,
+            "clicks": 1
+        }]));
+        let (status, _) = request(&db, Method::Get, "/bid/2/segments",
+                                  Value::Null, 250);
+        assert_eq!(status, 404);
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape("<a href=\"x\">Tom & Jerry's</a>"),
+                   "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s\
+                    &lt;/a&gt;");
+    }
+
+    #[test]
+    fn routing() {
+        let db = fixture();
+        let status = |method, url| {
+            request(&db, method, url, Value::Null, 0).0
+        };
+        assert_eq!(status(Method::Get, "/box/1/bids?page=1"), 200);
+        assert_eq!(status(Method::Get, "/box/x/bids"), 404);
+        assert_eq!(status(Method::Get, "/nowhere"), 404);
+        assert_eq!(status(Method::Put, "/bid/1"), 405);
+        assert_eq!(status(Method::Post, "/box/1/serve"), 405);
+    }
+}
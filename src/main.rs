@@ -1,2 +1,191 @@
+use project_brilliant_db::{
+    describe_db, expected_schema, open_db, AdBoxId
+};
+use project_brilliant_utilities::{Second, Timestamp, EPOCH};
+use scheduler::Scheduler;
+use std::{
+    env, error::Error, process, thread, time::{Duration, SystemTime}
+};
+
+mod admin;
+mod api;
+mod scheduler;
+
+const USAGE: &str = "\
+usage: project-brilliant <command> [<argument>...]
+
+commands:
+    init <database uri>
+        Create a database, or bring one's schema up to date.
+    add-advertiser <database uri> <name>
+    add-publisher <database uri> <name>
+        Add an advertiser or publisher, and print its id.
+    add-ad-box <database uri> <publisher id> <name> <width> <height>
+               <minimum bid> <increment> <fee percent>
+               [<house image url> <house link url>]
+        Add an ad box, and print its id. Bids are per day, like $5.10.
+    add-creative <database uri> <advertiser id> <width> <height>
+                 <image url> <link url> <alt text>
+        Add a creative, and print its id.
+    list <database uri> advertisers|publishers|ad-boxes|creatives
+        List everything of one kind.
+    place-bid <database uri> <ad box id> <advertiser id> <creative id>
+              <bid> <expense limit> <expiry unix time>
+        Place a bid, and print its id.
+    cancel-bid <database uri> <bid id>
+        Cancel a bid.
+    run-auction <database uri> <ad box id>
+        Charge for what an ad box has shown, and print what it will show.
+    balances <database uri>
+        Print what everyone has paid and earned, and check the ledger.
+    schema-diff <database uri>
+        Compare a database's schema with the one this version expects.
+    shown-at <database uri> <ad box id> <unix time>
+        Show what an ad box was showing at a point in time.
+    serve <database uri> <address>
+        Serve the HTTP API at an address, like localhost:8080.
+    schedule <database uri>
+        Settle every ad box as each of its segments ends, until killed.
+";
+
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["init", uri] => admin::init(uri),
+        ["add-advertiser", uri, name] => admin::add_advertiser(uri, name),
+        ["add-publisher", uri, name] => admin::add_publisher(uri, name),
+        ["add-ad-box", uri, rest @ ..] if rest.len() == 7 || rest.len() == 9
+            => admin::add_ad_box(uri, rest),
+        ["add-creative", uri, rest @ ..] if rest.len() == 6
+            => admin::add_creative(uri, rest),
+        ["list", uri, what] => admin::list(uri, what),
+        ["place-bid", uri, rest @ ..] if rest.len() == 6
+            => admin::place_bid(uri, rest),
+        ["cancel-bid", uri, bid] => admin::cancel_bid(uri, bid),
+        ["run-auction", uri, ad_box] => admin::run_auction(uri, ad_box),
+        ["balances", uri] => admin::balances(uri),
+        ["schema-diff", uri] => schema_diff(uri),
+        ["shown-at", uri, ad_box, at] => shown_at(uri, ad_box, at),
+        ["serve", uri, address] => serve(uri, address),
+        ["schedule", uri] => schedule(uri),
+        _ => {
+            eprint!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    match result {
+        Ok(true) => (),
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Print every way the database's schema differs from the expected one.
+/// Returns whether they're the same.
+fn schema_diff(uri: &str) -> Result<bool, Box<dyn Error>> {
+    let expected = expected_schema()?;
+    let found = describe_db(uri)?;
+    let differences = expected.diff(&found);
+    if differences.is_empty() {
+        println!("The schema is as expected (fingerprint {}).",
+                 found.fingerprint());
+    }
+    for difference in &differences {
+        println!("{}", difference);
+    }
+    Ok(differences.is_empty())
+}
+
+/// Print what an ad box was showing at a point in time. Returns whether it
+/// was showing anything.
+fn shown_at(uri: &str, ad_box: &str, at: &str)
+    -> Result<bool, Box<dyn Error>>
+{
+    let ad_box = AdBoxId(ad_box.parse()?);
+    let at = Timestamp::from(at.parse::<u64>()?);
+    let db = open_db(uri)?;
+    let seconds = |time: Timestamp| u64::from(time - EPOCH);
+    match db.shown_at(ad_box, at)? {
+        Some(showing) => {
+            println!("Bid {} (advertiser {}, creative {}) was shown from {} \
+                      until {}, at {}.",
+                     showing.bid.0, showing.advert.advertiser.0,
+                     showing.advert.creative.0, seconds(showing.start),
+                     seconds(showing.end), showing.rate.per_day());
+            Ok(true)
+        },
+        None => {
+            let settled_until = db.settled_until(ad_box)?;
+            if at < settled_until {
+                println!("Nothing was shown.");
+            } else {
+                println!("The auction has only been run until {}.",
+                         seconds(settled_until));
+            }
+            Ok(false)
+        }
+    }
+}
+
+/// Answer HTTP requests, one at a time, until killed.
+fn serve(uri: &str, address: &str) -> Result<bool, Box<dyn Error>> {
+    let db = open_db(uri)?;
+    let server = tiny_http::Server::http(address)
+                     .map_err(|e| e as Box<dyn Error>)?;
+    println!("Listening on {}.", server.server_addr());
+    for request in server.incoming_requests() {
+        if let Err(e) = api::respond(&db, request, now()) {
+            eprintln!("couldn't respond: {}", e);
+        }
+    }
+    Ok(true)
+}
+
+/// How long the scheduler goes between looking for bids that have changed.
+/// Changes settle their ad box first, so nothing's charged wrongly in the
+/// meantime; ad boxes just aren't settled as promptly.
+const RESCAN_INTERVAL_SECONDS: u64 = 60;
+
+/// Settle ad boxes as their segments end, until killed.
+fn schedule(uri: &str) -> Result<bool, Box<dyn Error>> {
+    let db = open_db(uri)?;
+    let mut scheduler = Scheduler::new();
+    loop {
+        match scheduler.rescan(&db) {
+            Ok(failed) => for (ad_box, e) in failed {
+                eprintln!("couldn't schedule ad box {}: {}", ad_box.0, e);
+            },
+            Err(e) => eprintln!("couldn't list the ad boxes: {}", e)
+        }
+        let rescan_at = now() + Second::from(RESCAN_INTERVAL_SECONDS);
+        loop {
+            let now = now();
+            for (ad_box, result) in scheduler.run_due(&db, now) {
+                match result {
+                    Ok(segments) => println!("Settled ad box {} ({} \
+                                              segments).",
+                                             ad_box.0, segments.len()),
+                    Err(e) => eprintln!("couldn't settle ad box {}: {}",
+                                        ad_box.0, e)
+                }
+            }
+            if now >= rescan_at {
+                break;
+            }
+            let wake = scheduler.next_due().map_or(rescan_at, |due| {
+                due.min(rescan_at)
+            });
+            thread::sleep(Duration::from_secs(u64::from(wake - now)));
+        }
+    }
+}
+
+fn now() -> Timestamp {
+    let since = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+                                 .unwrap_or_default();
+    EPOCH + since.as_secs().into()
 }
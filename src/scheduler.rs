@@ -0,0 +1,179 @@
+//! Settling ad boxes as their segments end.
+
+use infinite_auction::{BidId, Segment};
+use project_brilliant_db::{AdBoxId, DB, Error};
+use project_brilliant_utilities::Timestamp;
+use std::{cmp::Reverse, collections::{BinaryHeap, HashMap}};
+
+/// An ad box, and what was settled for it, or why it couldn't be.
+pub type Settled = (AdBoxId, Result<Vec<Segment<BidId>>, Error>);
+
+/// When each ad box's current segment ends, soonest first.
+///
+/// Nothing here is persisted: the database says where every ad box was
+/// last settled, which is all it takes to work the queue out again.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    queue: BinaryHeap<Reverse<(Timestamp, AdBoxId)>>,
+    /// When each ad box in the queue is due. Queue entries that don't match
+    /// are out of date, and are skipped.
+    due: HashMap<AdBoxId, Timestamp>
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler::default()
+    }
+
+    /// Work out when every ad box is next due, from the database: to fill
+    /// the queue on startup, and to notice bids that have changed since.
+    /// Returns the ad boxes that couldn't be worked out, and why; they're
+    /// left as they were.
+    pub fn rescan(
+        &mut self, db: &DB
+    ) -> Result<Vec<(AdBoxId, Error)>, Error> {
+        let mut failed = Vec::new();
+        for (ad_box, _) in db.ad_boxes()? {
+            if let Err(e) = self.reschedule(db, ad_box) {
+                failed.push((ad_box, e));
+            }
+        }
+        Ok(failed)
+    }
+
+    /// When the next ad box is due, if any are.
+    pub fn next_due(&mut self) -> Option<Timestamp> {
+        while let Some(&Reverse((at, ad_box))) = self.queue.peek() {
+            if self.due.get(&ad_box) == Some(&at) {
+                return Some(at);
+            }
+            self.queue.pop();
+        }
+        None
+    }
+
+    /// Settle each ad box whose segment has ended by `now`, up to the end
+    /// of that segment, and work out when it's next due. Ad boxes that
+    /// fell more than one segment behind are settled one segment at a
+    /// time.
+    ///
+    /// Returns what was settled for each ad box, in the order they were
+    /// due. An ad box that fails is dropped from the queue until the next
+    /// rescan.
+    pub fn run_due(&mut self, db: &DB, now: Timestamp) -> Vec<Settled> {
+        let mut settled = Vec::new();
+        while let Some(at) = self.next_due() {
+            if at > now {
+                break;
+            }
+            let Reverse((_, ad_box)) = self.queue.pop()
+                                           .expect("next_due peeked it");
+            self.due.remove(&ad_box);
+            let result = db.settle_ad_box(ad_box, at).and_then(|segments| {
+                self.reschedule(db, ad_box)?;
+                Ok(segments)
+            });
+            settled.push((ad_box, result));
+        }
+        settled
+    }
+
+    fn reschedule(&mut self, db: &DB, ad_box: AdBoxId) -> Result<(), Error> {
+        match db.segment_end(ad_box)? {
+            Some(end) => if self.due.insert(ad_box, end) != Some(end) {
+                self.queue.push(Reverse((end, ad_box)));
+            },
+            None => {
+                self.due.remove(&ad_box);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use infinite_auction::Bid;
+    use project_brilliant_db::testing::{at, bid, seeded};
+    use project_brilliant_utilities::{Currency, Token};
+
+    /// Two ad boxes, each with a bid worth `limit` tokens on it.
+    fn db(limits: [u64; 2]) -> (DB, [AdBoxId; 2], [BidId; 2]) {
+        let seeded = seeded();
+        let mut ad_boxes = [AdBoxId(0); 2];
+        let mut bids = [BidId(0); 2];
+        for i in 0..2 {
+            ad_boxes[i] = seeded.add_ad_box(&seeded.ad_box());
+            bids[i] = seeded.db.insert_bid(
+                ad_boxes[i], &bid(seeded.advert, 5, limits[i], 1000), at(0)
+            ).unwrap();
+        }
+        (seeded.db, ad_boxes, bids)
+    }
+
+    #[test]
+    fn settle_at_segment_ends() {
+        // Alone, each bid pays the minimum, so runs out after `limit`
+        // seconds.
+        let (db, [first, second], [first_bid, second_bid]) = db([200, 100]);
+        let mut scheduler = Scheduler::new();
+        assert_eq!(scheduler.next_due(), None);
+        assert!(scheduler.rescan(&db).unwrap().is_empty());
+        assert_eq!(scheduler.next_due(), Some(at(100)));
+        assert!(scheduler.run_due(&db, at(99)).is_empty());
+
+        let settled = scheduler.run_due(&db, at(150));
+        assert_eq!(settled.len(), 1);
+        let (ad_box, segments) = &settled[0];
+        assert_eq!(*ad_box, second);
+        assert_eq!(segments.as_ref().unwrap()[0].end, at(100));
+        assert_eq!(db.settled_until(second).unwrap(), at(100));
+        assert_eq!(db.get_bid(second_bid).unwrap().unwrap().spent,
+                   Token::from(100));
+        // Nothing's left to show in the second ad box.
+        assert_eq!(scheduler.next_due(), Some(at(200)));
+
+        // Starting again works the same queue out.
+        let mut restarted = Scheduler::new();
+        restarted.rescan(&db).unwrap();
+        assert_eq!(restarted.next_due(), Some(at(200)));
+
+        // Someone else settling an ad box leaves its entry early, which
+        // does no harm.
+        db.settle_ad_box(first, at(180)).unwrap();
+        let settled = scheduler.run_due(&db, at(500));
+        assert_eq!(settled.len(), 1);
+        assert_eq!(settled[0].0, first);
+        assert_eq!(settled[0].1.as_ref().unwrap()[0].end, at(200));
+        assert_eq!(db.get_bid(first_bid).unwrap().unwrap().spent,
+                   Token::from(200));
+        assert_eq!(scheduler.next_due(), None);
+        db.check_ledger().unwrap();
+    }
+
+    #[test]
+    fn rescan_sees_new_bids() {
+        let (db, [first, _], [first_bid, _]) = db([200, 100]);
+        let mut scheduler = Scheduler::new();
+        scheduler.rescan(&db).unwrap();
+        // Outbidding the first bid starts a new segment, which ends
+        // sooner.
+        db.settle_ad_box(first, at(50)).unwrap();
+        let stored = db.get_bid(first_bid).unwrap().unwrap();
+        db.insert_bid(first, &Bid {
+            bid: Currency::from(10),
+            expense_limit: stored.remaining(),
+            ..stored.bid
+        }, at(50)).unwrap();
+        scheduler.rescan(&db).unwrap();
+        // The new bid pays 6 a second to outbid the old one, so its 150
+        // tokens (what's left of the 200 limit it was copied from) run out
+        // at 75, before the second ad box's bid does at 100.
+        assert_eq!(scheduler.next_due(), Some(at(75)));
+        let settled = scheduler.run_due(&db, at(75));
+        assert_eq!(settled.len(), 1);
+        assert_eq!(settled[0].0, first);
+        assert_eq!(db.settled_until(first).unwrap(), at(75));
+    }
+}